@@ -1,26 +1,65 @@
 // cli/build.rs
 
-use std::fs;
-use std::path::Path;
-use std::process::exit;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::{env, fmt, fs, io};
 
 use clap::Args;
 use fshelpers::mkdir_p;
+use glob::Pattern;
 
-use super::CmdError;
 use super::clean::clean_lfs;
-use crate::config::CONFIG;
+use super::{CmdError, resolve_profile};
+use crate::config::{self, CONFIG};
 use crate::exec;
-use crate::profile::Profile;
-use crate::utils::time::timestamp;
+use crate::profile::{BuildDefaults, Profile, ProfileBuf};
+use crate::utils::cmd::{JOBS, SCRIPT_LOG_DIR, SCRIPT_TIMEOUT, jobs};
+use crate::utils::dl::DownloadOptions;
+use crate::utils::init::exit;
+use crate::utils::sign::sign;
+use crate::utils::time::{TIMESTAMP_FORMAT, epoch_timestamp, parse_timestamp, timestamp};
 
-#[derive(Args, Debug)]
+/// Marker recording the fingerprint of the last passing requirements check
+const REQS_MARKER: &str = "/tmp/lfstage/reqs";
+
+#[derive(Args, Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Cmd {
-    pub profile: String,
+    /// The profile to build
+    ///
+    /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config. With
+    /// `--profile-from-file`, this is the stagefile path instead.
+    pub profile: Option<String>,
+
+    /// Build the profile in this directory instead of an installed one
+    ///
+    /// The directory is laid out like an installed profile. Sources and stage files are cached in
+    /// `.cache` within it.
+    #[arg(long, value_name = "DIR")]
+    pub profile_from_file: Option<PathBuf>,
 
     /// The absolute path to save the stagefile to
     pub stagefile: Option<String>,
 
+    /// The directory to save the stagefile to, keeping the default file name
+    ///
+    /// Ignored if the stagefile path is given
+    #[arg(long, value_name = "DIR")]
+    pub dest_dir: Option<String>,
+
+    /// The file name to save the stagefile as, keeping the default directory
+    ///
+    /// `.tar.xz` is appended if missing. Ignored if the stagefile path is given.
+    #[arg(long, value_name = "NAME", value_parser = parse_stage_name)]
+    pub stage_name: Option<String>,
+
+    /// The timestamp to use instead of the current time, e.g. `2025-07-04_13-37-00`
+    ///
+    /// Defaults to `SOURCE_DATE_EPOCH` if it's set. Used in the default stagefile name and written
+    /// to the profile's timestamp file, so identical builds get identical names.
+    #[arg(long, value_name = "TIMESTAMP", value_parser = parse_timestamp_arg)]
+    pub timestamp: Option<String>,
+
     /// Don't actually do anything
     #[arg(short, long)]
     pub dry: bool,
@@ -28,12 +67,87 @@ pub struct Cmd {
     /// Don't strip all binaries
     ///
     /// All libraries and executables get stripped with --strip-unneeded
-    #[arg(short, long)]
+    #[arg(short, long, alias = "no-strip", overrides_with = "no_skip_strip")]
     pub skip_strip: bool,
 
-    /// Don't check system requirements
+    /// Strip binaries even if the profile skips stripping
+    #[arg(long, overrides_with = "skip_strip")]
+    pub no_skip_strip: bool,
+
+    /// Assume the system meets requirements instead of checking them
+    #[arg(long, alias = "skip-reqs", overrides_with = "no_assume_reqs")]
+    pub assume_reqs: bool,
+
+    /// Check requirements even if the profile assumes them
+    #[arg(long, overrides_with = "assume_reqs")]
+    pub no_assume_reqs: bool,
+
+    /// Sum the sizes of all sources up front and show an aggregate progress bar
+    #[arg(long, overrides_with = "no_show_total")]
+    pub show_total: bool,
+
+    /// Don't show an aggregate progress bar, even if the profile shows one
+    #[arg(long, overrides_with = "show_total")]
+    pub no_show_total: bool,
+
+    /// Only retry the sources that failed during the previous download
+    #[arg(long)]
+    pub resume_download: bool,
+
+    /// Only download and set up the sources listed in this file, one destination per line
+    ///
+    /// Listed sources the profile doesn't register are warned about and ignored
+    #[arg(long, value_name = "FILE")]
+    pub only_sources: Option<PathBuf>,
+
+    /// Retry a failed build script up to N times before giving up
+    ///
+    /// Only use this for profiles whose scripts are safe to rerun after a partial failure
+    #[arg(long, value_name = "N")]
+    pub retry_failed_scripts: Option<usize>,
+
+    /// Run build scripts in the same group concurrently, up to the number of jobs at once
+    ///
+    /// A script's group is the number its name starts with, so `05a-zlib` and `05b-xz` run
+    /// together, and `06-gcc` waits for both. Only use this if scripts in a group are independent.
+    #[arg(long, overrides_with = "no_concurrent_scripts")]
+    pub concurrent_scripts: bool,
+
+    /// Run build scripts one at a time, even if the profile runs them concurrently
+    #[arg(long, overrides_with = "concurrent_scripts")]
+    pub no_concurrent_scripts: bool,
+
+    /// Don't write a `.sha256` sidecar with the stage file's digest next to it
     #[arg(long)]
-    pub skip_reqs: bool,
+    pub no_checksum: bool,
+
+    /// Sign the stage file with `signing_key` from the config, writing a detached signature next
+    /// to it
+    #[arg(long)]
+    pub sign: bool,
+
+    /// The number of jobs scripts are told to use, overriding `jobs` from the config
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// Kill a script that runs longer than this many seconds, overriding `script_timeout` from the
+    /// config
+    ///
+    /// Zero disables the timeout
+    #[arg(long, value_name = "SECS")]
+    pub timeout: Option<u64>,
+
+    /// The directory to log the output of each script to
+    ///
+    /// Defaults to `<script_log_dir>/<profile>` from the config
+    #[arg(long, value_name = "DIR")]
+    pub log_dir: Option<PathBuf>,
+
+    /// Export an extra environment variable to build scripts, e.g. `--env CFLAGS=-O2`
+    ///
+    /// May be given more than once. These override the profile's environment.
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    pub env: Vec<(String, String)>,
 }
 
 impl Cmd {
@@ -42,36 +156,79 @@ impl Cmd {
     /// The build subcommand builds a stage file and accepts a variety of arguments.
     ///
     /// # Arguments
-    /// * `self.profile`    - The profile to build, defaults to "x86_64-glibc-tox".
-    /// * `self.stagefile`  - The path to the built stagefile, defaults to "/var/cache/lfstage/stages/lfstage-<profile>-<timestamp>.tar.xz".
+    /// * `self.profile`    - The profile to build, defaults to `LFSTAGE_PROFILE`, then `CONFIG.default_profile`.
+    /// * `self.profile_from_file` - A profile directory to build instead of an installed profile.
+    /// * `self.stagefile`  - The path to the built stagefile, defaults to "<stages dir>/lfstage-<profile>-<timestamp>.tar.xz".
+    /// * `self.dest_dir`   - The directory for the default stagefile name, if `self.stagefile` isn't set.
+    /// * `self.stage_name` - The stagefile name, if `self.stagefile` isn't set.
+    /// * `self.timestamp`  - The build timestamp, defaults to `SOURCE_DATE_EPOCH`, then the current time.
     /// * `self.dry`        - If true, perform a dry run, building nothing.
     ///
-    /// * `self.skip_reqs`  - Don't check system requirements
+    /// * `self.assume_reqs` - Don't check system requirements
     /// * `self.skip_strip` - Don't strip binaries
+    /// * `self.show_total` - Show an aggregate progress bar for source downloads
+    /// * `self.resume_download` - Only retry previously failed source downloads
+    /// * `self.only_sources` - Only use the sources listed in this file
+    /// * `self.retry_failed_scripts` - Retry failed build scripts this many times
+    /// * `self.concurrent_scripts` - Run build scripts in the same group concurrently
+    /// * `self.log_dir` - Log the output of each script here, defaults to `CONFIG.script_log_dir/<profile>`
+    /// * `self.jobs` - The number of jobs, defaults to `CONFIG.jobs`
+    ///
+    /// Flags that aren't given default to the `[build]` section of the profile's `profile.toml`.
     ///
     /// # Errors
     /// This function returns a `CmdError` if:
     /// - The script directory couldn't be read.
     /// - One of the scripts failed.
     pub async fn run(&self) -> Result<(), CmdError> {
-        let profile = Profile::new(&self.profile);
-        let timestamp = timestamp();
+        // A profile from a directory isn't named, so the only positional left is the stagefile
+        let (profile, stagefile) = match &self.profile_from_file {
+            | Some(_) if self.stagefile.is_some() => return Err(CmdError::InvalidArgument("a profile can't be given with --profile-from-file".to_string())),
+            | Some(dir) => (ProfileBuf::from_dir(dir)?, self.profile.clone()),
+            | None => (resolve_profile(self.profile.as_ref())?, self.stagefile.clone()),
+        };
+        config::apply_profile_overrides(&profile.config_overrides_file());
+        let cmd = Self {
+            stagefile,
+            ..self.with_profile_defaults(&profile.config().build)
+        };
+        let timestamp = cmd.timestamp()?;
+
+        if let Some(jobs) = cmd.jobs.filter(|&j| j > 0) {
+            JOBS.get_or_init(|| jobs);
+        }
+
+        if let Some(timeout) = cmd.timeout {
+            SCRIPT_TIMEOUT.get_or_init(|| timeout);
+        }
 
         // Get the path to which the stage file should be saved. Can be overridden if the stagefile
-        // positional argument is set.
-        let stagefile = match &self.stagefile {
-            | Some(path) => path.clone(),
-            | None => format!("/var/cache/lfstage/profiles/{profile}/stages/lfstage-{profile}-{timestamp}.tar.xz"),
+        // positional argument is set, placed in another directory with `--dest-dir`, or renamed with
+        // `--stage-name`.
+        let stagefilename = cmd.stage_name.clone().unwrap_or_else(|| format!("lfstage-{profile}-{timestamp}.tar.xz"));
+        let stagefile = match (&cmd.stagefile, &cmd.dest_dir) {
+            | (Some(path), _) => path.clone(),
+            | (None, Some(dir)) => Path::new(dir).join(&stagefilename).to_string_lossy().to_string(),
+            | (None, None) => profile.stages_dir().join(&stagefilename).to_string_lossy().to_string(),
         };
 
+        let log_dir = cmd.log_dir.clone().unwrap_or_else(|| Path::new(&CONFIG.script_log_dir).join(profile.name()));
+
         // Write some variables to files in `profile_tmpdir` to be accessed later:
         // * `timestamp`    - The timestamp is written to `timestamp`
         // * `stagefile`    - The name of the stagefile is written to `stagefilename`
-        // * `strip`        - If we're stripping, create the file `strip`
-        if !self.dry {
+        // * `strip`        - If we're stripping, create the file `strip` listing any exclusions
+        if !cmd.dry {
             // set up `profile_tmpdir`
             mkdir_p(profile.tmp_dir())?;
 
+            // set up the destination directory
+            if cmd.stagefile.is_none()
+                && let Some(dir) = &cmd.dest_dir
+            {
+                mkdir_p(dir)?;
+            }
+
             // timestamp
             fs::write(profile.timestamp_file(), &timestamp)?;
 
@@ -79,16 +236,18 @@ impl Cmd {
             fs::write(profile.stagefilename_file(), &stagefile)?;
 
             // strip
-            if !self.skip_strip && CONFIG.strip {
-                fshelpers::mkf(profile.tmp_dir().join("strip"))?;
-            }
+            Strip::resolve(cmd.skip_strip).write_marker(&profile)?;
+
+            // per-script logs
+            mkdir_p(&log_dir)?;
+            SCRIPT_LOG_DIR.get_or_init(|| log_dir.clone());
         }
 
         // The directory for profile-specific scripts
         let scriptdir = &profile.scripts_dir();
 
         // Display what would be done
-        if self.dry {
+        if cmd.dry {
             println!(
                 "Would build profile '{profile}' and save it to '{stagefile}' by executing scripts in '{}' and '/usr/lib/lfstage/scripts/'",
                 scriptdir.display(),
@@ -96,39 +255,253 @@ impl Cmd {
             return Ok(())
         }
 
+        cmd.log_plan(&profile, &stagefile, &log_dir);
+
         // Check requirements
-        if !self.skip_reqs {
-            check_reqs(profile);
+        if cmd.assume_reqs {
+            warn!("Assuming the system meets requirements for '{profile}'");
+        } else {
+            check_reqs(&profile);
         }
 
-        // TODO: Add profile-specific reqs.sh support
-
         // Prepare for the build by cleaning and copying over sources
-        clean_lfs()?;
-        profile.download_sources(false).await?;
-        profile.setup_sources()?;
+        clean_lfs(false)?;
+        let only = cmd.only_sources.as_deref().map(|list| profile.select_sources(list)).transpose()?;
+        profile
+            .download_sources(&DownloadOptions {
+                show_total: cmd.show_total,
+                resume: cmd.resume_download,
+                include: only.iter().flatten().filter_map(|dest| Pattern::new(&Pattern::escape(dest)).ok()).collect(),
+                ..Default::default()
+            })
+            .await?;
+        profile.setup_sources(only.as_deref())?;
 
         // Build
-        profile.run_build_scripts();
-
-        // TODO: Add signing. Write lfstage metadata to /etc/lfstage-release before saving.
+        profile.run_build_scripts(cmd.retry_failed_scripts.unwrap_or(0), cmd.concurrent_scripts, &cmd.env);
 
         // Save the stage file
-        profile.save_stagefile()?;
+        profile.write_release_file(&timestamp)?;
+        let stagefile = profile.save_stagefile(!cmd.no_checksum)?;
+        if cmd.sign {
+            sign(&stagefile)?;
+        }
 
         Ok(())
     }
 }
 
-fn check_reqs(profile: &Profile) {
-    let custom_reqs = format!("/var/lib/lfstage/profiles/{profile}/reqs.sh");
-    let reqs_script = match Path::new(&custom_reqs).exists() {
-        | true => custom_reqs.as_str(),
-        | false => "/usr/lib/lfstage/scripts/reqs.sh",
+/// # Validates a timestamp given on the command line
+fn parse_timestamp_arg(s: &str) -> Result<String, String> {
+    match parse_timestamp(s) {
+        | Some(_) => Ok(s.to_string()),
+        | None => Err(format!("Invalid timestamp '{s}', expected the format '{TIMESTAMP_FORMAT}'")),
+    }
+}
+
+/// # Parses an environment variable given on the command line as `KEY=VALUE`
+///
+/// The key must be a valid shell variable name. The value may be empty.
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = s.split_once('=') else {
+        return Err(format!("Invalid environment variable '{s}', expected KEY=VALUE"))
     };
 
-    if let Err(e) = exec!(&profile; reqs_script) {
-        error!("System does not meet requirements: {e}");
-        exit(1)
+    let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match valid {
+        | true => Ok((key.to_string(), value.to_string())),
+        | false => Err(format!("Invalid environment variable name '{key}'")),
+    }
+}
+
+/// # Validates a stagefile name, appending the extension if it's missing
+fn parse_stage_name(name: &str) -> Result<String, String> {
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(format!("Invalid stagefile name '{name}', expected a file name without a directory"));
+    }
+
+    match name.ends_with(".tar.xz") {
+        | true => Ok(name.to_string()),
+        | false => Ok(format!("{name}.tar.xz")),
+    }
+}
+
+/// # Whether binaries get stripped before the stage file is saved
+#[derive(Debug, PartialEq, Eq)]
+enum Strip {
+    /// Strip every binary
+    All,
+
+    /// Strip nothing
+    None,
+
+    /// Strip every binary except those under these paths, relative to `$LFS`
+    Except(Vec<String>),
+}
+
+impl Strip {
+    /// # Resolves the strip decision from the command line and config
+    ///
+    /// `--skip-strip` takes precedence over the config. Exclusions come from `strip_exclude`.
+    fn resolve(skip_strip: bool) -> Self {
+        if skip_strip || !CONFIG.strip {
+            return Self::None
+        }
+
+        match CONFIG.strip_exclude.is_empty() {
+            | true => Self::All,
+            | false => Self::Except(CONFIG.strip_exclude.clone()),
+        }
+    }
+
+    /// # Writes the marker read by save.sh
+    ///
+    /// The marker lists one excluded path per line. It's removed when nothing should be stripped, so
+    /// a marker left over from a previous build can't cause stripping.
+    fn write_marker(&self, profile: &Profile) -> std::io::Result<()> {
+        let marker = profile.strip_file();
+        match self {
+            | Self::None if marker.exists() => fs::remove_file(marker),
+            | Self::None => Ok(()),
+            | Self::All => fs::write(marker, ""),
+            | Self::Except(paths) => fs::write(marker, paths.join("\n") + "\n"),
+        }
+    }
+}
+
+impl fmt::Display for Strip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Self::All => write!(f, "strip all binaries"),
+            | Self::None => write!(f, "strip nothing"),
+            | Self::Except(paths) => write!(f, "strip all binaries except under {}", paths.join(", ")),
+        }
+    }
+}
+
+impl Cmd {
+    /// # Gets the build timestamp
+    ///
+    /// `--timestamp` takes precedence over `SOURCE_DATE_EPOCH`, which takes precedence over the
+    /// current time. A malformed `SOURCE_DATE_EPOCH` is an error rather than being ignored, since
+    /// the build wouldn't be reproducible.
+    fn timestamp(&self) -> io::Result<String> {
+        if let Some(timestamp) = &self.timestamp {
+            return Ok(timestamp.clone())
+        }
+
+        let Some(epoch) = env::var("SOURCE_DATE_EPOCH").ok().filter(|e| !e.trim().is_empty()) else {
+            return Ok(timestamp())
+        };
+
+        let timestamp = epoch
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .and_then(epoch_timestamp)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid SOURCE_DATE_EPOCH '{epoch}'")))?;
+        debug!("Using timestamp {timestamp} from SOURCE_DATE_EPOCH");
+        Ok(timestamp)
+    }
+
+    /// # Fills in the flags that weren't given on the command line from the profile's defaults
+    ///
+    /// A `--no-*` flag keeps a default the profile enables from applying.
+    fn with_profile_defaults(&self, defaults: &BuildDefaults) -> Self {
+        Self {
+            assume_reqs: self.assume_reqs || (!self.no_assume_reqs && defaults.assume_reqs),
+            concurrent_scripts: self.concurrent_scripts || (!self.no_concurrent_scripts && defaults.concurrent_scripts),
+            jobs: self.jobs.or(defaults.jobs),
+            retry_failed_scripts: self.retry_failed_scripts.or(defaults.retry_failed_scripts),
+            show_total: self.show_total || (!self.no_show_total && defaults.show_total),
+            skip_strip: self.skip_strip || (!self.no_skip_strip && defaults.skip_strip),
+            ..self.clone()
+        }
+    }
+
+    /// # Logs what the build intends to do
+    ///
+    /// This makes the log self-explanatory when it's reviewed after the fact.
+    fn log_plan(&self, profile: &Profile, stagefile: &str, log_dir: &Path) {
+        let sources = profile.read_dls().map_or_else(|_| "unknown".to_string(), |dls| dls.len().to_string());
+        let scripts = profile.collect_build_scripts().len();
+
+        info!("Build plan for '{profile}':");
+        info!("  stagefile: {stagefile}");
+        info!("  sources:   {sources}");
+        info!("  scripts:   {scripts}");
+        info!("  jobs:      {}", jobs());
+        info!("  strip:     {}", Strip::resolve(self.skip_strip));
+        info!("  reqs:      {}", if self.assume_reqs { "assumed" } else { "checked" });
+        info!("  logs:      {}", log_dir.display());
+    }
+}
+
+/// # Checks that the system meets the requirements for a profile
+///
+/// The global reqs.sh runs first, followed by the profile's own `reqs.sh` if it has one, see
+/// [`profile_reqs`].
+fn check_reqs(profile: &Profile) {
+    let reqs_scripts = reqs_scripts(profile);
+
+    // Skip the check if it already passed for an identical configuration
+    let fingerprint = reqs_fingerprint(profile, &reqs_scripts);
+    if fs::read_to_string(REQS_MARKER).is_ok_and(|m| m == fingerprint) {
+        info!("Requirements already met for '{profile}', skipping check");
+        return
+    }
+
+    for reqs_script in &reqs_scripts {
+        if let Err(e) = exec!(&profile; reqs_script) {
+            error!("System does not meet requirements: {e}");
+            exit(1)
+        }
+    }
+
+    if let Err(e) = fs::write(REQS_MARKER, &fingerprint) {
+        warn!("Failed to write requirements marker '{REQS_MARKER}': {e}");
+    }
+}
+
+/// # Lists every script a build runs, in the order it runs them
+///
+/// These are the requirements scripts, clean.sh, the profile's build scripts, and save.sh. This
+/// mirrors [`Cmd::run`], so it must be kept in sync with it.
+pub fn plan(profile: &Profile) -> Vec<PathBuf> {
+    let mut plan = reqs_scripts(profile);
+    plan.push(PathBuf::from("/usr/lib/lfstage/scripts/clean.sh"));
+    plan.extend(profile.collect_build_scripts());
+    plan.push(PathBuf::from("/usr/lib/lfstage/scripts/save.sh"));
+    plan
+}
+
+/// # Lists the requirements scripts, the global one first
+fn reqs_scripts(profile: &Profile) -> Vec<PathBuf> {
+    let mut reqs_scripts = vec![PathBuf::from("/usr/lib/lfstage/scripts/reqs.sh")];
+    reqs_scripts.extend(profile_reqs(profile));
+    reqs_scripts
+}
+
+/// # Finds the profile's own requirements script
+///
+/// This is `reqs.sh` in the profile's scripts directory. It isn't numbered, so it never runs as a
+/// build script. Profiles that ship it at the top of the profile directory are supported too.
+fn profile_reqs(profile: &Profile) -> Option<PathBuf> {
+    [profile.scripts_dir(), profile.profile_lib_dir()]
+        .into_iter()
+        .map(|dir| dir.join("reqs.sh"))
+        .find(|p| p.is_file())
+}
+
+/// # Fingerprints the inputs of a requirements check
+///
+/// The fingerprint is stored in [`REQS_MARKER`] after a passing check. If any of the values that
+/// could affect the outcome change, the fingerprint no longer matches and the check is rerun.
+fn reqs_fingerprint(profile: &Profile, reqs_scripts: &[PathBuf]) -> String {
+    let mut fingerprint = format!("profile={profile}\n");
+    for script in reqs_scripts {
+        let _ = writeln!(fingerprint, "script={}", script.display());
     }
+    let _ = write!(fingerprint, "jobs={}\nversion={}\n", jobs(), env!("CARGO_PKG_VERSION"));
+    fingerprint
 }
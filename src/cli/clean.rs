@@ -1,29 +1,223 @@
 // cli/clean.rs
 
-use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
 
 use clap::Args;
+use indicatif::HumanBytes;
 
+use super::{CmdError, resolve_profile};
 use crate::exec;
+use crate::profile::MOUNT_DIR;
 
 #[derive(Args, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Cmd {
+    /// List what would be removed without removing anything
     #[arg(short, long)]
     pub dry: bool,
+
+    /// The profile whose sources or stages to remove
+    ///
+    /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config
+    #[arg(short, long)]
+    pub profile: Option<String>,
+
+    /// Remove the profile's downloaded sources instead of cleaning the build root
+    #[arg(long)]
+    pub sources: bool,
+
+    /// Remove the profile's cached stage files instead of cleaning the build root
+    ///
+    /// Their symlinks in `/var/cache/lfstage/stages` are removed too
+    #[arg(long)]
+    pub stages: bool,
+
+    /// Remove the profile's sources and stages, and clean the build root
+    #[arg(short, long)]
+    pub all: bool,
 }
 
 impl Cmd {
-    // TODO: Add options to clean a specific profile. Cleaning should remove unregistered source
-    // files, and optionally all source files, in addition to running `clean_lfs()`.
-    pub fn run(&self) -> Result<(), super::CmdError> {
-        if self.dry {
-            println!("Would recursively unmount and remove the contents of /var/lib/lfstage/mount");
-            return Ok(())
+    /// # Runs the clean subcommand
+    ///
+    /// Without flags, this cleans the build root like [`clean_lfs`]. With `--sources` or
+    /// `--stages`, it only removes those for the profile. `--all` does everything.
+    pub fn run(&self) -> Result<(), CmdError> {
+        let sources = self.sources || self.all;
+        let stages = self.stages || self.all;
+
+        if sources || stages {
+            let profile = resolve_profile(self.profile.as_ref())?;
+            if sources {
+                clean_dir(&profile.sources_dir(), "sources", self.dry)?;
+            }
+            if stages {
+                clean_stage_links(&profile.stages_dir(), self.dry)?;
+                clean_dir(&profile.stages_dir(), "stages", self.dry)?;
+            }
+        }
+
+        if self.all || !(sources || stages) {
+            clean_lfs(self.dry)?;
         }
 
-        clean_lfs()?;
         Ok(())
     }
 }
 
-pub fn clean_lfs() -> io::Result<()> { exec!("/usr/lib/lfstage/scripts/clean.sh") }
+/// # An entry in the build root that cleaning removes
+struct Entry {
+    path:  PathBuf,
+    bytes: u64,
+}
+
+/// # Unmounts and removes the contents of the build root
+///
+/// Each removed entry is logged at debug level, followed by a summary. If `dry` is set, the entries
+/// are only listed.
+pub fn clean_lfs(dry: bool) -> Result<(), CmdError> {
+    let entries = removable()?;
+    let bytes = entries.iter().map(|e| e.bytes).sum::<u64>();
+
+    if dry {
+        println!("Would recursively unmount and remove the following from {MOUNT_DIR}:");
+        for entry in &entries {
+            println!("    {:>12}  {}", HumanBytes(entry.bytes).to_string(), entry.path.display());
+        }
+        println!("Would free {}", HumanBytes(bytes));
+        return Ok(())
+    }
+
+    exec!("/usr/lib/lfstage/scripts/clean.sh")?;
+
+    for entry in &entries {
+        debug!("Removed '{}' ({})", entry.path.display(), HumanBytes(entry.bytes));
+    }
+    info!("Removed {} entries from {MOUNT_DIR}, freeing {}", entries.len(), HumanBytes(bytes));
+    Ok(())
+}
+
+/// # Removes the contents of a profile directory, like its sources or stages
+///
+/// Each removed entry is logged at debug level, followed by a summary naming `what` was removed.
+/// If `dry` is set, the entries are only listed.
+fn clean_dir(dir: &Path, what: &str, dry: bool) -> Result<(), CmdError> {
+    let entries = dir_entries(dir)?;
+    let bytes = entries.iter().map(|e| e.bytes).sum::<u64>();
+
+    if dry {
+        println!("Would remove the following {what} from {}:", dir.display());
+        for entry in &entries {
+            println!("    {:>12}  {}", HumanBytes(entry.bytes).to_string(), entry.path.display());
+        }
+        println!("Would free {}", HumanBytes(bytes));
+        return Ok(())
+    }
+
+    for entry in &entries {
+        match entry.path.is_dir() && !entry.path.is_symlink() {
+            | true => fs::remove_dir_all(&entry.path)?,
+            | false => fs::remove_file(&entry.path)?,
+        }
+        debug!("Removed '{}' ({})", entry.path.display(), HumanBytes(entry.bytes));
+    }
+    info!(
+        "Removed {} entries of {what} from {}, freeing {}",
+        entries.len(),
+        dir.display(),
+        HumanBytes(bytes)
+    );
+    Ok(())
+}
+
+/// # Removes the convenience symlinks save.sh created for the stages in a directory
+///
+/// The symlinks live in `/var/cache/lfstage/stages` and are named after the stages. Links that
+/// point anywhere else, such as at another profile's stage of the same name, are left alone. If
+/// `dry` is set, they're only listed.
+fn clean_stage_links(stages_dir: &Path, dry: bool) -> io::Result<()> {
+    if !stages_dir.is_dir() {
+        return Ok(())
+    }
+
+    let links_dir = Path::new("/var/cache/lfstage/stages");
+    for stage in fs::read_dir(stages_dir)?.map_while(Result::ok) {
+        let link = links_dir.join(stage.file_name());
+        let Ok(target) = fs::read_link(&link) else { continue };
+
+        // save.sh creates relative links, so they're resolved against the directory they're in
+        let points_at_stage = fs::canonicalize(links_dir.join(target)).is_ok_and(|target| fs::canonicalize(stage.path()).is_ok_and(|stage| target == stage));
+        if !points_at_stage {
+            continue
+        }
+
+        if dry {
+            println!("Would remove '{}'", link.display());
+            continue
+        }
+        fs::remove_file(&link)?;
+        debug!("Removed '{}'", link.display());
+    }
+    Ok(())
+}
+
+/// # Lists the entries of a directory with their sizes
+fn dir_entries(dir: &Path) -> io::Result<Vec<Entry>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new())
+    }
+
+    let dev = fs::metadata(dir)?.dev();
+    let mut entries = fs::read_dir(dir)?
+        .map_while(Result::ok)
+        .map(|e| {
+            let path = e.path();
+            let bytes = disk_usage(&path, dev);
+            Entry { path, bytes }
+        })
+        .collect::<Vec<_>>();
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// # Lists what cleaning removes from the build root
+///
+/// Like clean.sh, this skips dotfiles at the top level. Anything mounted inside the build root is
+/// unmounted rather than removed, so it doesn't count toward the sizes.
+fn removable() -> io::Result<Vec<Entry>> {
+    let mount = Path::new(MOUNT_DIR);
+    if !mount.is_dir() {
+        return Ok(Vec::new())
+    }
+
+    let dev = fs::metadata(mount)?.dev();
+    let mut entries = fs::read_dir(mount)?
+        .map_while(Result::ok)
+        .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+        .map(|e| {
+            let path = e.path();
+            let bytes = disk_usage(&path, dev);
+            Entry { path, bytes }
+        })
+        .collect::<Vec<_>>();
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// # Sums the sizes of the files under a path, without crossing into other filesystems
+fn disk_usage(path: &Path, dev: u64) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else { return 0 };
+    if meta.dev() != dev {
+        return 0
+    }
+
+    if !meta.is_dir() {
+        return meta.len()
+    }
+
+    fs::read_dir(path).map_or(0, |dir| dir.map_while(Result::ok).map(|e| disk_usage(&e.path(), dev)).sum())
+}
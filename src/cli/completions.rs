@@ -0,0 +1,23 @@
+// cli/completions.rs
+
+use std::io;
+
+use clap::{Args, CommandFactory};
+use clap_complete::{Shell, generate};
+
+use super::Cli;
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// The shell to generate completions for
+    #[arg(value_enum)]
+    pub shell: Shell,
+}
+
+impl Cmd {
+    /// # Runs the completions subcommand
+    ///
+    /// The completions subcommand prints a completion script for a shell to stdout, e.g.
+    /// `lfstage completions bash > /usr/share/bash-completion/completions/lfstage`.
+    pub fn run(&self) { generate(self.shell, &mut Cli::command(), "lfstage", &mut io::stdout()) }
+}
@@ -1,13 +1,26 @@
 // cli/build.rs
 
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
 use clap::Args;
+use futures::future::join_all;
+use glob::Pattern;
+use indicatif::HumanBytes;
 
-use super::CmdError;
+use super::{CmdError, resolve_profile};
 use crate::profile::Profile;
+use crate::utils::dl::{Download, DownloadOptions, content_length};
+use crate::utils::time::parse_timestamp;
 
 #[derive(Args, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Cmd {
-    pub profile: String,
+    /// The profile to download sources for
+    ///
+    /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config
+    pub profile: Option<String>,
 
     /// Whether to forcibly download sources
     #[arg(short, long)]
@@ -16,6 +29,50 @@ pub struct Cmd {
     /// Whether to perform a dry-run
     #[arg(short, long)]
     pub dry: bool,
+
+    /// Whether to sum the sizes of all sources up front and show an aggregate progress bar
+    #[arg(long)]
+    pub show_total: bool,
+
+    /// Whether to only retry the sources that failed during the previous download
+    #[arg(long)]
+    pub resume_download: bool,
+
+    /// Only download sources whose destination matches this glob
+    ///
+    /// May be passed more than once. Includes are applied before excludes.
+    #[arg(long, value_name = "GLOB", value_parser = Pattern::new)]
+    pub include: Vec<Pattern>,
+
+    /// Don't download sources whose destination matches this glob
+    ///
+    /// May be passed more than once
+    #[arg(long, value_name = "GLOB", value_parser = Pattern::new)]
+    pub exclude: Vec<Pattern>,
+
+    /// Write a JSON report of what was fetched, skipped, or failed to this path
+    #[arg(long, value_name = "PATH")]
+    pub dump_report: Option<PathBuf>,
+
+    /// Only download existing sources again if they changed upstream since the last build
+    #[arg(long)]
+    pub since_last_build: bool,
+
+    /// Revalidate existing sources with their stored `ETag`s, downloading them again if they changed
+    ///
+    /// Sources without a stored `ETag` fall back to `--since-last-build`, if given
+    #[arg(long)]
+    pub etags: bool,
+
+    /// Abort the remaining downloads as soon as one source fails
+    #[arg(long)]
+    pub fail_fast: bool,
+
+    /// Refuse to download sources that don't match the checksums recorded in `sources.lock`
+    ///
+    /// Sources missing from the lockfile are refused too
+    #[arg(long)]
+    pub frozen: bool,
 }
 
 impl Cmd {
@@ -24,7 +81,7 @@ impl Cmd {
     /// The download subcommand downloads the sources for a stage file profile.
     ///
     /// # Arguments
-    /// * `self.profile`    - The profile to target, defaults to "x86_64-glibc-tox-stage2".
+    /// * `self.profile`    - The profile to target, defaults to `LFSTAGE_PROFILE`, then `CONFIG.default_profile`.
     /// * `self.dry`        - If true, perform a dry run, building nothing.
     ///
     /// # Errors
@@ -32,27 +89,97 @@ impl Cmd {
     /// - The script directory couldn't be read.
     /// - One of the scripts failed.
     pub async fn run(&self) -> Result<(), CmdError> {
-        let profile = Profile::new(&self.profile);
+        let profile = resolve_profile(self.profile.as_ref())?;
 
         if !profile.sources_file().exists() {
-            error!("Sources list for profile '{}' does not exist", self.profile);
+            error!("Sources list for profile '{profile}' does not exist");
             return Err(CmdError::MissingComponent(profile.sources_file()));
         }
 
-        if self.dry {
-            let dls = profile.read_dls()?;
-            println!("Would download the following to '{}':", profile.sources_dir().display());
-
-            for dl in &dls {
-                println!("    {dl}");
-            }
+        let opts = DownloadOptions {
+            force:      self.force,
+            show_total: self.show_total,
+            resume:     self.resume_download,
+            include:    self.include.clone(),
+            exclude:    self.exclude.clone(),
+            report:     self.dump_report.clone(),
+            since:      self.since_last_build.then(|| last_build(&profile)).flatten(),
+            etags:      self.etags,
+            fail_fast:  self.fail_fast,
+            frozen:     self.frozen,
+            retries:    None,
+        };
 
+        if self.dry {
+            let mut dls = profile.read_dls()?;
+            opts.filter(&mut dls);
+            self.show_plan(&profile, &dls).await;
             return Ok(())
         }
 
         info!("Downloading sources for '{profile}'");
-        profile.download_sources(self.force).await?;
+        profile.download_sources(&opts).await?;
         info!("Downloaded sources for '{profile}'");
         Ok(())
     }
 }
+
+impl Cmd {
+    /// # Prints what would be downloaded, with the estimated size of each source
+    ///
+    /// Sizes are fetched concurrently with `HEAD` requests on a best-effort basis. Sources that
+    /// already exist are shown as cached, unless `--force` is passed.
+    async fn show_plan(&self, profile: &Profile, dls: &[Download]) {
+        let sources_dir = profile.sources_dir();
+        let pending = |dl: &Download| self.force || !sources_dir.join(&dl.dest).exists();
+
+        let sizes = join_all(dls.iter().map(|dl| async move {
+            match pending(dl) && dl.git_ref.is_none() {
+                | true => Some(content_length(&dl.url).await),
+                | false => None,
+            }
+        }))
+        .await;
+
+        println!("Would download the following to '{}':", sources_dir.display());
+
+        let (mut total, mut count, mut unknown) = (0, 0, 0);
+        for (dl, size) in dls.iter().zip(sizes) {
+            let size = match size {
+                // Repositories are fetched every time and their size isn't known up front
+                | None if dl.git_ref.is_some() => {
+                    count += 1;
+                    "repository".to_string()
+                },
+                | None => "cached".to_string(),
+                | Some(None) => {
+                    count += 1;
+                    unknown += 1;
+                    "unknown".to_string()
+                },
+                | Some(Some(bytes)) => {
+                    count += 1;
+                    total += bytes;
+                    HumanBytes(bytes).to_string()
+                },
+            };
+            println!("    {size:>12}  {dl}");
+        }
+
+        println!("Estimated total: {} across {count} sources", HumanBytes(total));
+        if unknown > 0 {
+            println!("Could not determine the size of {unknown} sources");
+        }
+    }
+}
+
+/// # Reads the time of the last build of a profile
+///
+/// Returns `None` if the profile hasn't been built, or the recorded timestamp is invalid.
+fn last_build(profile: &Profile) -> Option<SystemTime> {
+    let since = fs::read_to_string(profile.timestamp_file()).ok().and_then(|ts| parse_timestamp(ts.trim()));
+    if since.is_none() {
+        warn!("No previous build of '{profile}' recorded, checking sources normally");
+    }
+    since
+}
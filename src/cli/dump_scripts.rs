@@ -0,0 +1,34 @@
+// cli/dump_scripts.rs
+
+use clap::Args;
+
+use super::{CmdError, build, resolve_profile};
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// The profile whose script plan to print
+    ///
+    /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config
+    pub profile: Option<String>,
+}
+
+impl Cmd {
+    /// # Runs the dump-scripts subcommand
+    ///
+    /// The dump-scripts subcommand prints every script a build of a profile runs, in order, one path
+    /// per line and nothing else, so the plans of two profiles can be diffed. This includes the
+    /// global scripts and requirements checks, not just the profile's build scripts.
+    pub fn run(&self) -> Result<(), CmdError> {
+        let profile = resolve_profile(self.profile.as_ref())?;
+
+        if !profile.scripts_dir().exists() {
+            return Err(CmdError::MissingComponent(profile.scripts_dir()));
+        }
+
+        for script in build::plan(&profile) {
+            println!("{}", script.display());
+        }
+
+        Ok(())
+    }
+}
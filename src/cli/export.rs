@@ -1,42 +1,107 @@
 // cli/export.rs
 
-use std::fs::write;
+use std::fs::{remove_file, write};
+use std::path::Path;
 
-use clap::Args;
+use clap::{Args, ValueEnum};
 use fshelpers::mkdir_p;
 
+use super::resolve_profile;
 use crate::exec;
-use crate::profile::Profile;
+use crate::utils::sign::sign;
 
 #[derive(Args, Debug)]
 pub struct Cmd {
     /// The profile to export
-    pub profile: String,
+    ///
+    /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config
+    pub profile: Option<String>,
 
     /// An optional destination for the exported tarball
     pub out: Option<String>,
 
+    /// Export the profile as a plain directory tree instead of a tarball
+    #[arg(short, long)]
+    pub raw: bool,
+
     /// Whether to perform a dry-run
     #[arg(short, long)]
     pub dry: bool,
+
+    /// Sign the tarball with `signing_key` from the config, writing a detached signature next to it
+    #[arg(long, conflicts_with = "raw")]
+    pub sign: bool,
+
+    /// How to compress the tarball
+    ///
+    /// zstd is much faster than xz for large profiles, at the cost of a somewhat larger tarball
+    #[arg(long, value_enum, default_value_t = ExportFormat::Xz, conflicts_with = "raw")]
+    pub format: ExportFormat,
+}
+
+/// # The compression of an exported tarball
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ExportFormat {
+    Xz,
+    Zst,
+    Gz,
+    None,
+}
+
+impl ExportFormat {
+    /// # The name passed to export.sh as `LFSTAGE_EXPORT_FORMAT`
+    const fn name(self) -> &'static str {
+        match self {
+            | Self::Xz => "xz",
+            | Self::Zst => "zst",
+            | Self::Gz => "gz",
+            | Self::None => "none",
+        }
+    }
+
+    /// # The extension of a tarball in this format
+    const fn extension(self) -> &'static str {
+        match self {
+            | Self::Xz => "tar.xz",
+            | Self::Zst => "tar.zst",
+            | Self::Gz => "tar.gz",
+            | Self::None => "tar",
+        }
+    }
 }
 
 impl Cmd {
     pub fn run(&self) -> Result<(), super::CmdError> {
-        let profile = Profile::new(&self.profile);
-        let out = self
-            .out
-            .clone()
-            .unwrap_or_else(|| format!("/var/cache/lfstage/profiles/{}.tar.xz", &profile.name));
+        let profile = resolve_profile(self.profile.as_ref())?;
+        let out = self.out.clone().unwrap_or_else(|| match self.raw {
+            | true => format!("/var/cache/lfstage/exports/{}", profile.name()),
+            | false => format!("/var/cache/lfstage/profiles/{}.{}", profile.name(), self.format.extension()),
+        });
 
         if self.dry {
-            println!("Would run /usr/lib/lfstage/scripts/export.sh with profile '{profile}' and destination '{out}'");
+            let kind = match self.raw {
+                | true => "raw".to_string(),
+                | false => format!("{} tarball", self.format.name()),
+            };
+            println!("Would run /usr/lib/lfstage/scripts/export.sh with profile '{profile}' and {kind} destination '{out}'");
             return Ok(())
         }
 
         mkdir_p("/tmp/lfstage")?;
         write("/tmp/lfstage/export", &out)?;
-        exec!(profile; "/usr/lib/lfstage/scripts/export.sh")?;
+
+        // The export script copies the tree as is if this marker exists
+        let raw_marker = Path::new("/tmp/lfstage/export-raw");
+        if self.raw {
+            write(raw_marker, "")?;
+        } else if raw_marker.exists() {
+            remove_file(raw_marker)?;
+        }
+        let env = [("LFSTAGE_EXPORT_FORMAT".to_string(), self.format.name().to_string())];
+        exec!(&profile; "/usr/lib/lfstage/scripts/export.sh"; &env)?;
+        if self.sign {
+            sign(Path::new(&out))?;
+        }
 
         info!("Exported '{profile}' to '{out}'");
         println!("Exported '{profile}' to '{out}'");
@@ -1,39 +1,193 @@
 // cli/import.rs
 
-use std::fs::write;
+use std::fmt;
+use std::fs::{remove_file, write};
+use std::path::Path;
 
 use clap::Args;
 use fshelpers::mkdir_p;
 
 use crate::exec;
+use crate::utils::dl::fetch;
 
 #[derive(Args, Debug)]
 pub struct Cmd {
-    /// The path to the profile tarball to import
+    /// Where to import the profile from
     ///
-    /// TODO: Also support tarball urls and github repos
-    pub r#in: String,
+    /// This may be a local tarball or directory, a tarball URL, a git URL, or
+    /// `github:owner/repo[@ref]`, where the ref is a branch or tag. Commits can't be cloned.
+    #[arg(value_parser = parse_source)]
+    pub r#in: ImportSource,
+
+    /// Import a plain directory tree instead of a tarball
+    ///
+    /// The profile is named after the directory, minus any `-lfstage` suffix
+    #[arg(short, long)]
+    pub raw: bool,
 
     /// Whether to perform a dry-run
     #[arg(short, long)]
     pub dry: bool,
 }
 
+/// # Where a profile is imported from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImportSource {
+    /// A local tarball or directory
+    Local(String),
+
+    /// A tarball to download
+    Tarball(String),
+
+    /// A git repository to clone
+    Git(String),
+
+    /// A GitHub repository to clone, optionally at a branch or tag, but not a commit
+    GitHub {
+        repo:  String,
+        r#ref: Option<String>,
+    },
+}
+
+impl fmt::Display for ImportSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Self::Local(s) | Self::Tarball(s) | Self::Git(s) => write!(f, "{s}"),
+            | Self::GitHub { repo, r#ref: None } => write!(f, "github:{repo}"),
+            | Self::GitHub { repo, r#ref: Some(r) } => write!(f, "github:{repo}@{r}"),
+        }
+    }
+}
+
+/// # Parses where to import a profile from
+///
+/// URLs whose last segment looks like a tarball are downloaded, other URLs are cloned with git, and
+/// anything else is taken as a local path.
+fn parse_source(s: &str) -> Result<ImportSource, String> {
+    if let Some(rest) = s.strip_prefix("github:") {
+        let (repo, r#ref) = match rest.split_once('@') {
+            | Some((repo, r)) => (repo, Some(r)),
+            | None => (rest, None),
+        };
+
+        let valid = repo.split('/').count() == 2 && repo.split('/').all(|p| !p.is_empty());
+        if !valid || r#ref.is_some_and(str::is_empty) {
+            return Err(format!("Invalid GitHub repo '{s}', expected github:owner/repo[@ref]"));
+        }
+
+        return Ok(ImportSource::GitHub {
+            repo:  repo.to_string(),
+            r#ref: r#ref.map(str::to_string),
+        })
+    }
+
+    if !s.contains("://") {
+        return Ok(ImportSource::Local(s.to_string()))
+    }
+
+    let name = s.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    match [".tar", ".tgz", ".txz"].iter().any(|ext| name.contains(ext)) {
+        | true => Ok(ImportSource::Tarball(s.to_string())),
+        | false => Ok(ImportSource::Git(s.to_string())),
+    }
+}
+
 impl Cmd {
-    pub fn run(&self) -> Result<(), super::CmdError> {
-        let input = &self.r#in;
+    /// # Runs the import subcommand
+    ///
+    /// Tarballs are downloaded to a temporary file in `/tmp/lfstage` first, which is removed once
+    /// the import is done or fails. Whatever was given is then normalized to a local path or git
+    /// URL, which is written to `/tmp/lfstage/import` for import.sh.
+    pub async fn run(&self) -> Result<(), super::CmdError> {
+        let source = &self.r#in;
         if self.dry {
-            println!("Would run /usr/lib/lfstage/scripts/import.sh with import '{input}'");
+            let kind = if self.raw { "raw " } else { "" };
+            println!("Would run /usr/lib/lfstage/scripts/import.sh with {kind}import '{source}'");
             return Ok(())
         }
 
         mkdir_p("/tmp/lfstage")?;
-        write("/tmp/lfstage/import", input)?;
+
+        // The import script clones at this ref if this marker exists
+        let ref_marker = Path::new("/tmp/lfstage/import-ref");
+        if ref_marker.exists() {
+            remove_file(ref_marker)?;
+        }
+
+        // The downloaded tarball, if any, is removed when this is dropped
+        let (input, _download) = match source {
+            // import.sh runs from the profiles directory, so relative paths would resolve against it
+            | ImportSource::Local(path) => (Path::new(path).canonicalize()?.to_string_lossy().to_string(), None),
+            | ImportSource::Git(url) => (url.clone(), None),
+            | ImportSource::GitHub { repo, r#ref } => {
+                if let Some(r) = r#ref {
+                    write(ref_marker, r)?;
+                }
+                (format!("https://github.com/{repo}.git"), None)
+            },
+            | ImportSource::Tarball(url) => {
+                let download = tempfile::Builder::new().prefix("import.").tempfile_in("/tmp/lfstage")?;
+                info!("Downloading '{url}' to '{}'", download.path().display());
+                fetch(url, download.path()).await?;
+                (download.path().to_string_lossy().to_string(), Some(download))
+            },
+        };
+
+        write("/tmp/lfstage/import", &input)?;
+
+        // The import script copies the tree as is if this marker exists
+        let raw_marker = Path::new("/tmp/lfstage/import-raw");
+        if self.raw {
+            write(raw_marker, "")?;
+        } else if raw_marker.exists() {
+            remove_file(raw_marker)?;
+        }
         exec!("/usr/lib/lfstage/scripts/import.sh")?;
 
-        info!("Imported profile from '{input}'");
-        println!("Imported profile from '{input}'");
+        info!("Imported profile from '{source}'");
+        println!("Imported profile from '{source}'");
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{ImportSource, parse_source};
+
+    #[test]
+    fn import_source_kinds() {
+        let local = |s: &str| ImportSource::Local(s.to_string());
+        assert_eq!(parse_source("./x86_64-glibc.tar.xz"), Ok(local("./x86_64-glibc.tar.xz")));
+        assert_eq!(parse_source("/srv/profiles/glibc-lfstage"), Ok(local("/srv/profiles/glibc-lfstage")));
+
+        let url = "https://example.org/profiles/glibc.tar.xz";
+        assert_eq!(parse_source(url), Ok(ImportSource::Tarball(url.to_string())));
+
+        let url = "https://git.example.org/tox/glibc-lfstage.git";
+        assert_eq!(parse_source(url), Ok(ImportSource::Git(url.to_string())));
+    }
+
+    #[test]
+    fn import_source_github() {
+        assert_eq!(
+            parse_source("github:toxikuu/x86_64-glibc-tox-stage2-lfstage"),
+            Ok(ImportSource::GitHub {
+                repo:  "toxikuu/x86_64-glibc-tox-stage2-lfstage".to_string(),
+                r#ref: None,
+            })
+        );
+        assert_eq!(
+            parse_source("github:tox/glibc@v1.2"),
+            Ok(ImportSource::GitHub {
+                repo:  "tox/glibc".to_string(),
+                r#ref: Some("v1.2".to_string()),
+            })
+        );
+
+        assert!(parse_source("github:tox").is_err());
+        assert!(parse_source("github:tox/glibc/extra").is_err());
+        assert!(parse_source("github:/glibc").is_err());
+        assert!(parse_source("github:tox/glibc@").is_err());
+    }
+}
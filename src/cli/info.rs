@@ -0,0 +1,100 @@
+// cli/info.rs
+
+use std::fs;
+
+use chrono::{DateTime, Local};
+use clap::Args;
+
+use super::{CmdError, resolve_profile};
+use crate::profile::Profile;
+use crate::utils::size::format_size;
+use crate::utils::time::TIMESTAMP_FORMAT;
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// The profile to show
+    ///
+    /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config
+    pub profile: Option<String>,
+}
+
+impl Cmd {
+    /// # Runs the info subcommand
+    ///
+    /// The info subcommand prints a profile's paths, its registered sources, its build scripts in
+    /// the order they'd run, and its cached stage files. It only reads, so it doesn't need root.
+    pub fn run(&self) -> Result<(), CmdError> {
+        let profile = resolve_profile(self.profile.as_ref())?;
+        if !profile.profile_lib_dir().exists() {
+            return Err(CmdError::MissingComponent(profile.profile_lib_dir()));
+        }
+
+        println!("Profile {profile}");
+        println!("  lib dir:     {}", profile.profile_lib_dir().display());
+        println!("  scripts dir: {}", profile.scripts_dir().display());
+        println!("  sources dir: {}", profile.sources_dir().display());
+        println!("  stages dir:  {}", profile.stages_dir().display());
+
+        println!();
+        print_sources(&profile);
+
+        println!();
+        print_scripts(&profile);
+
+        println!();
+        print_stages(&profile)
+    }
+}
+
+/// # Prints the sources the profile registers
+fn print_sources(profile: &Profile) {
+    // get_registered_sources exits if the sources file can't be read
+    let sources = match profile.sources_file().exists() {
+        | true => profile.get_registered_sources(None),
+        | false => Vec::new(),
+    };
+
+    println!("Sources ({}):", sources.len());
+    for source in sources {
+        println!("  {source}");
+    }
+}
+
+/// # Prints the build scripts in the order they'd run
+fn print_scripts(profile: &Profile) {
+    // collect_build_scripts exits if the scripts directory can't be read
+    let scripts = match profile.scripts_dir().is_dir() {
+        | true => profile.collect_build_scripts(),
+        | false => Vec::new(),
+    };
+
+    println!("Build scripts ({}):", scripts.len());
+    for script in scripts {
+        let name = script.file_name().unwrap_or_default().to_string_lossy();
+        println!("  {name}");
+    }
+}
+
+/// # Prints the cached stage files with their sizes and modification times
+fn print_stages(profile: &Profile) -> Result<(), CmdError> {
+    let stages_dir = profile.stages_dir();
+    let mut stages = match stages_dir.exists() {
+        | true => fs::read_dir(&stages_dir)?
+            .map_while(Result::ok)
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".tar.xz"))
+            .filter_map(|e| Some((e.file_name().to_string_lossy().to_string(), e.metadata().ok()?)))
+            .collect::<Vec<_>>(),
+        | false => Vec::new(),
+    };
+    stages.sort_by(|a, b| a.0.cmp(&b.0));
+
+    println!("Stages ({}):", stages.len());
+    for (name, meta) in stages {
+        let modified = meta
+            .modified()
+            .map_or_else(|_| "unknown".to_string(), |t| DateTime::<Local>::from(t).format(TIMESTAMP_FORMAT).to_string());
+        println!("  {name}  {:>10}  {modified}", format_size(meta.len()));
+    }
+
+    Ok(())
+}
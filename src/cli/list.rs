@@ -6,6 +6,8 @@ use std::path::Path;
 use clap::Args;
 
 use super::CmdError;
+use crate::config::CONFIG;
+use crate::profile::Profile;
 
 #[derive(Args, Debug)]
 pub struct Cmd {
@@ -13,32 +15,47 @@ pub struct Cmd {
     ///
     /// If empty, all profiles are listed, though in less detail
     pub profile: Option<String>,
+
+    /// Also show whether each profile has sources, how many build scripts it has, and whether it
+    /// has a cached stage
+    #[arg(short, long)]
+    pub verbose: bool,
 }
 
 impl Cmd {
-    // TODO: Refactor, making use of the profile struct here once it exists
+    /// # Runs the list subcommand
+    ///
+    /// Profiles are read from `/var/lib/lfstage/profiles`. The one matching `default_profile` is
+    /// marked with an asterisk.
     pub fn run(&self) -> Result<(), CmdError> {
         match &self.profile {
             | Some(p) => {
-                let profile_path = Path::new("/var/lib/lfstage/profiles").join(p);
-                if profile_path.exists() {
-                    println!("{p} at {} exists", profile_path.display());
-                } else {
+                let profile = Profile::new(p);
+                let profile_path = profile.profile_lib_dir();
+                if !profile_path.exists() {
                     println!("{p} at {} does not exist", profile_path.display());
+                    return Ok(())
                 }
+
+                println!("{p} at {} exists", profile_path.display());
+                print_details(profile);
             },
             | None => {
-                let all_profiles = fs::read_dir("/var/lib/lfstage/profiles")?
+                let mut names = fs::read_dir("/var/lib/lfstage/profiles")?
                     .map_while(Result::ok)
-                    .map(|p| p.path())
-                    .filter(|p| p.is_dir())
+                    .filter(|e| e.path().is_dir())
+                    .map(|e| e.file_name().to_string_lossy().to_string())
                     .collect::<Vec<_>>();
+                names.sort();
 
                 println!("Available profiles:");
-                for profile_path in all_profiles {
-                    #[allow(clippy::expect_used)]
-                    let profile = profile_path.file_name().expect("Profile should have a name").to_string_lossy();
-                    println!("{profile} at {}", profile_path.display());
+                for name in &names {
+                    let profile = Profile::new(name);
+                    let marker = if *name == CONFIG.default_profile { "*" } else { " " };
+                    println!("{marker} {name} at {}", profile.profile_lib_dir().display());
+                    if self.verbose {
+                        print_details(profile);
+                    }
                 }
             },
         }
@@ -46,3 +63,24 @@ impl Cmd {
         Ok(())
     }
 }
+
+/// # Prints whether a profile has sources, its number of build scripts, and whether it has a stage
+fn print_details(profile: &Profile) {
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+    // collect_build_scripts exits if there's no scripts directory, which is fine when building but
+    // not when listing
+    let scripts = match profile.scripts_dir().is_dir() {
+        | true => profile.collect_build_scripts().len(),
+        | false => 0,
+    };
+
+    println!("    sources:       {}", yes_no(profile.sources_file().exists()));
+    println!("    build scripts: {scripts}");
+    println!("    cached stage:  {}", yes_no(has_stage(&profile.stages_dir())));
+}
+
+/// # Whether a stages directory holds at least one stage file
+fn has_stage(stages_dir: &Path) -> bool {
+    fs::read_dir(stages_dir).is_ok_and(|mut entries| entries.any(|e| e.is_ok_and(|e| e.file_name().to_string_lossy().ends_with(".tar.xz"))))
+}
@@ -1,19 +1,33 @@
 pub mod build;
 pub mod clean;
+pub mod completions;
 pub mod download;
+pub mod dump_scripts;
 pub mod export;
 pub mod import;
+pub mod info;
 pub mod list;
+pub mod paths;
+pub mod profiles;
+pub mod prune_stages;
+pub mod run_script;
+pub mod selftest;
+pub mod sources;
+pub mod validate_sources;
 
-use std::io;
 use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::{env, io};
 
 use clap::builder::Styles;
 use clap::builder::styling::AnsiColor;
 use clap::{Parser, Subcommand};
 use thiserror::Error;
 
-use crate::utils::dl::DownloadError;
+use crate::config::CONFIG;
+use crate::profile::ProfileBuf;
+use crate::utils::cmd::{KEEP_ENV_FILE, ScriptError};
+use crate::utils::dl::{DownloadError, REQUIRE_CHECKSUM};
 
 const STYLES: Styles = Styles::styled()
     .header(AnsiColor::Cyan.on_default().bold())
@@ -36,6 +50,24 @@ const STYLES: Styles = Styles::styled()
 pub struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Read the config from this path instead of /etc/lfstage/config.toml
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Keep the environment file scripts are executed with instead of deleting it
+    ///
+    /// The file is left at `/tmp/lfstage/<profile>/bashenv` so it can be sourced when debugging
+    #[arg(long, global = true)]
+    keep_env_file: bool,
+
+    /// Refuse sources without a checksum, in addition to `require_checksum` from the config
+    #[arg(long, global = true)]
+    require_checksum: bool,
+
+    /// Don't color console logs, overriding `log_color` from the config
+    #[arg(long, global = true)]
+    pub no_color: bool,
 }
 
 #[derive(Subcommand)]
@@ -44,9 +76,22 @@ enum Commands {
     Build(build::Cmd),
     Clean(clean::Cmd),
     List(list::Cmd),
+    Info(info::Cmd),
     Import(import::Cmd),
     Export(export::Cmd),
     Download(download::Cmd),
+    ValidateSources(validate_sources::Cmd),
+    DumpScripts(dump_scripts::Cmd),
+    #[command(alias = "gc")]
+    PruneStages(prune_stages::Cmd),
+    Profiles(profiles::Cmd),
+    Selftest(selftest::Cmd),
+    #[command(alias = "run")]
+    RunScript(run_script::Cmd),
+    #[command(alias = "dump-paths")]
+    Paths(paths::Cmd),
+    Sources(sources::Cmd),
+    Completions(completions::Cmd),
 }
 
 #[rustfmt::skip]
@@ -55,8 +100,8 @@ pub enum CmdError {
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
 
-    // #[error("Invalid argument: {0}")]
-    // InvalidArgument(String),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
 
     #[error("Missing component: {0}")]
     MissingComponent(PathBuf),
@@ -64,19 +109,115 @@ pub enum CmdError {
     #[error("Download error: {0}")]
     Download(#[from] DownloadError),
 
-    // #[error("Script failed: {0}")]
-    // Command(String),
+    #[error("Script error: {0}")]
+    Script(#[from] ScriptError),
+
+    #[error("{0} source(s) failed validation")]
+    InvalidSources(usize),
+
+    #[error("No profile specified")]
+    NoProfile,
+
+    #[error("{0} self test(s) failed")]
+    SelftestFailed(usize),
+
+    #[error("'{}' matches several scripts: {}", .0, .1.join(", "))]
+    AmbiguousScript(String, Vec<String>),
 }
 
 impl Cli {
+    /// # Whether the subcommand needs root
+    ///
+    /// Only subcommands that just inspect profiles don't, along with dry runs, which only print
+    /// what they'd do. Everything else touches privileged paths or runs scripts against `$LFS`.
+    pub const fn needs_root(&self) -> bool {
+        match &self.command {
+            | Commands::List(_)
+            | Commands::Info(_)
+            | Commands::Paths(_)
+            | Commands::DumpScripts(_)
+            | Commands::ValidateSources(_)
+            | Commands::Completions(_) => false,
+            | Commands::Build(cmd) => !cmd.dry,
+            | Commands::Clean(cmd) => !cmd.dry,
+            | Commands::Download(cmd) => !cmd.dry,
+            | Commands::Export(cmd) => !cmd.dry,
+            | Commands::Import(cmd) => !cmd.dry,
+            | Commands::PruneStages(cmd) => !cmd.dry,
+            | Commands::Profiles(profiles::Cmd {
+                command: profiles::ProfilesCommand::Migrate { dry },
+            }) => !*dry,
+            | _ => true,
+        }
+    }
+
+    /// # Prints shell completions if that's the subcommand, returning whether it was
+    ///
+    /// This runs before logging is set up, so no log lines end up in the completion script.
+    pub fn print_completions(&self) -> bool {
+        match &self.command {
+            | Commands::Completions(cmd) => {
+                cmd.run();
+                true
+            },
+            | _ => false,
+        }
+    }
+
     pub async fn run(&self) -> Result<(), CmdError> {
+        KEEP_ENV_FILE.store(self.keep_env_file, Ordering::Relaxed);
+        REQUIRE_CHECKSUM.store(self.require_checksum, Ordering::Relaxed);
+
         match &self.command {
             | Commands::Build(cmd) => cmd.run().await,
             | Commands::Clean(cmd) => cmd.run(),
             | Commands::List(cmd) => cmd.run(),
-            | Commands::Import(cmd) => cmd.run(),
+            | Commands::Info(cmd) => cmd.run(),
+            | Commands::Import(cmd) => cmd.run().await,
             | Commands::Export(cmd) => cmd.run(),
             | Commands::Download(cmd) => cmd.run().await,
+            | Commands::ValidateSources(cmd) => cmd.run().await,
+            | Commands::DumpScripts(cmd) => cmd.run(),
+            | Commands::PruneStages(cmd) => cmd.run(),
+            | Commands::Profiles(cmd) => cmd.run(),
+            | Commands::Selftest(cmd) => cmd.run(),
+            | Commands::RunScript(cmd) => cmd.run(),
+            | Commands::Paths(cmd) => cmd.run(),
+            | Commands::Sources(cmd) => cmd.run(),
+            | Commands::Completions(cmd) => {
+                cmd.run();
+                Ok(())
+            },
         }
     }
 }
+
+/// # Resolves the name of the profile to operate on
+///
+/// The profile is chosen with the following precedence:
+/// 1. The explicit profile argument
+/// 2. The `LFSTAGE_PROFILE` environment variable
+/// 3. `default_profile` from the config
+///
+/// An empty `LFSTAGE_PROFILE` is treated as unset.
+///
+/// # Errors
+/// Returns `CmdError::NoProfile` if the resolved name is empty, rather than operating on the
+/// profiles directory itself.
+pub fn resolve_profile(arg: Option<&String>) -> Result<ProfileBuf, CmdError> {
+    let profile = match arg {
+        | Some(profile) => profile.clone(),
+        | None => env::var("LFSTAGE_PROFILE")
+            .ok()
+            .filter(|p| !p.trim().is_empty())
+            .unwrap_or_else(|| CONFIG.default_profile.clone()),
+    };
+
+    if profile.trim().is_empty() {
+        error!("No profile given and `default_profile` is not set");
+        error!("Pass a profile or set `default_profile` in /etc/lfstage/config.toml");
+        return Err(CmdError::NoProfile);
+    }
+
+    Ok(ProfileBuf::from(profile))
+}
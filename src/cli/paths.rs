@@ -0,0 +1,65 @@
+// cli/paths.rs
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+use clap::Args;
+use serde::Serialize;
+
+use super::{CmdError, resolve_profile};
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// The profile whose paths to print
+    ///
+    /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config
+    pub profile: Option<String>,
+}
+
+/// # A resolved profile path
+#[derive(Debug, Serialize)]
+struct PathInfo {
+    path:   PathBuf,
+    exists: bool,
+}
+
+impl Cmd {
+    /// # Runs the paths subcommand
+    ///
+    /// The paths subcommand prints every resolved path of a profile as a JSON object keyed by role,
+    /// so external tools don't have to hardcode lfstage's path conventions.
+    pub fn run(&self) -> Result<(), CmdError> {
+        let profile = resolve_profile(self.profile.as_ref())?;
+
+        let paths = [
+            ("lib_dir", profile.profile_lib_dir()),
+            ("cache_dir", profile.profile_cache_dir()),
+            ("tmp_dir", profile.tmp_dir()),
+            ("envs_dir", profile.envs_dir()),
+            ("scripts_dir", profile.scripts_dir()),
+            ("sources_file", profile.sources_file()),
+            ("config_file", profile.config_file()),
+            ("config_overrides_file", profile.config_overrides_file()),
+            ("sources_dir", profile.sources_dir()),
+            ("stages_dir", profile.stages_dir()),
+            ("failed_sources_file", profile.failed_sources_file()),
+            ("source_urls_file", profile.source_urls_file()),
+            ("source_etags_file", profile.source_etags_file()),
+            ("sources_lock_file", profile.sources_lock_file()),
+            ("stagefilename_file", profile.stagefilename_file()),
+            ("timestamp_file", profile.timestamp_file()),
+            ("strip_file", profile.strip_file()),
+        ]
+        .into_iter()
+        .map(|(role, path)| {
+            let exists = path.exists();
+            (role, PathInfo { path, exists })
+        })
+        .collect::<BTreeMap<_, _>>();
+
+        let json = serde_json::to_string_pretty(&paths).map_err(io::Error::other)?;
+        println!("{json}");
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+// cli/profiles.rs
+
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Subcommand};
+use fshelpers::mkdir_p;
+
+use super::CmdError;
+use crate::profile::Profile;
+use crate::utils::time::parse_stagefile_timestamp;
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    #[command(subcommand)]
+    pub command: ProfilesCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfilesCommand {
+    /// Move files laid out under old conventions to where lfstage now expects them
+    Migrate {
+        /// Whether to perform a dry-run
+        #[arg(short, long)]
+        dry: bool,
+    },
+}
+
+impl Cmd {
+    pub fn run(&self) -> Result<(), CmdError> {
+        match &self.command {
+            | ProfilesCommand::Migrate { dry } => migrate(*dry),
+        }
+    }
+}
+
+/// # A single migration step
+#[derive(Debug)]
+struct Migration {
+    from: PathBuf,
+    to:   PathBuf,
+    link: PathBuf,
+}
+
+/// # Migrates profiles to the current path conventions
+///
+/// Stage files used to be saved directly to `/var/cache/lfstage/stages/`. They now live in each
+/// profile's `stages_dir`, with a symlink left in `/var/cache/lfstage/stages/`.
+fn migrate(dry: bool) -> Result<(), CmdError> {
+    let migrations = collect_stage_migrations()?;
+    if migrations.is_empty() {
+        println!("Nothing to migrate");
+        return Ok(())
+    }
+
+    for m in &migrations {
+        if dry {
+            println!("Would move '{}' to '{}' and link it back", m.from.display(), m.to.display());
+            continue
+        }
+
+        if let Some(parent) = m.to.parent() {
+            mkdir_p(parent)?;
+        }
+        fs::rename(&m.from, &m.to)?;
+        symlink(&m.link, &m.from)?;
+        info!("Moved '{}' to '{}'", m.from.display(), m.to.display());
+    }
+
+    if !dry {
+        info!("Migrated {} stage files", migrations.len());
+    }
+
+    Ok(())
+}
+
+/// # Finds stage files saved under the old global stages directory
+fn collect_stage_migrations() -> Result<Vec<Migration>, CmdError> {
+    let old_stages = Path::new("/var/cache/lfstage/stages");
+    if !old_stages.exists() {
+        return Ok(Vec::new())
+    }
+
+    let profiles = fs::read_dir("/var/lib/lfstage/profiles")?
+        .map_while(Result::ok)
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+
+    let mut migrations = Vec::new();
+    for entry in fs::read_dir(old_stages)?.map_while(Result::ok) {
+        let path = entry.path();
+        if path.is_symlink() || !path.is_file() {
+            continue
+        }
+
+        let filename = entry.file_name().to_string_lossy().to_string();
+        if parse_stagefile_timestamp(&filename).is_none() {
+            continue
+        }
+
+        // Profile names may share prefixes, so prefer the longest match
+        let Some(profile) = profiles
+            .iter()
+            .filter(|p| filename.starts_with(&format!("lfstage-{p}-")))
+            .max_by_key(|p| p.len())
+        else {
+            warn!("Could not determine the profile for '{}', leaving it", path.display());
+            continue
+        };
+
+        migrations.push(Migration {
+            to:   Profile::new(profile).stages_dir().join(&filename),
+            link: Path::new("../profiles").join(profile).join("stages").join(&filename),
+            from: path,
+        });
+    }
+
+    Ok(migrations)
+}
@@ -0,0 +1,164 @@
+// cli/prune_stages.rs
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use clap::{ArgGroup, Args};
+
+use super::{CmdError, resolve_profile};
+use crate::utils::time::{parse_duration, parse_stagefile_timestamp};
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("policy").required(true).multiple(true).args(["keep", "older_than"])))]
+pub struct Cmd {
+    /// The profile whose stages to prune
+    ///
+    /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config
+    pub profile: Option<String>,
+
+    /// Keep at least this many of the newest stages
+    #[arg(short, long, value_name = "N")]
+    pub keep: Option<usize>,
+
+    /// Only remove stages older than this, e.g. `30d`, `12h`, or `2w`
+    #[arg(short, long, alias = "since", value_name = "DURATION", value_parser = parse_duration)]
+    pub older_than: Option<Duration>,
+
+    /// Whether to perform a dry-run
+    #[arg(short, long, alias = "dry-run")]
+    pub dry: bool,
+}
+
+/// Files saved next to a stage file, which are removed along with it
+const SIDECARS: [&str; 3] = [".sha256", ".sig", ".minisig"];
+
+impl Cmd {
+    /// # Runs the prune-stages subcommand
+    ///
+    /// The prune-stages subcommand removes old stage files for a profile. The newest `keep` stages
+    /// are always kept, and if `older_than` is set, only stages older than it are removed.
+    ///
+    /// # Errors
+    /// This function returns a `CmdError` if:
+    /// - The stages directory could not be read.
+    /// - A stage file could not be removed.
+    pub fn run(&self) -> Result<(), CmdError> {
+        let profile = resolve_profile(self.profile.as_ref())?;
+
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for (stage, age) in self.collect_prunable(&profile.stages_dir(), now)? {
+            let sidecars = sidecars(&stage);
+            if self.dry {
+                println!("Would remove '{}' ({} days old)", stage.display(), age.as_secs() / 86400);
+                for sidecar in &sidecars {
+                    println!("Would remove '{}'", sidecar.display());
+                }
+                continue
+            }
+
+            fs::remove_file(&stage)?;
+            info!("Removed '{}'", stage.display());
+
+            for sidecar in &sidecars {
+                fs::remove_file(sidecar)?;
+                debug!("Removed '{}'", sidecar.display());
+            }
+
+            // Remove the convenience symlink created by save.sh
+            if let Some(filename) = stage.file_name() {
+                let link = Path::new("/var/cache/lfstage/stages").join(filename);
+                if link.is_symlink() {
+                    fs::remove_file(&link)?;
+                }
+            }
+            removed += 1;
+        }
+
+        if !self.dry {
+            info!("Pruned {removed} stages for '{profile}'");
+        }
+
+        Ok(())
+    }
+
+    /// # Collects the stages that should be removed, along with their ages
+    ///
+    /// Only `.tar.xz` files count as stages, so sidecars don't take up places kept by `keep`.
+    fn collect_prunable(&self, stages_dir: &Path, now: SystemTime) -> Result<Vec<(PathBuf, Duration)>, CmdError> {
+        if !stages_dir.exists() {
+            return Ok(Vec::new())
+        }
+
+        let mut stages = fs::read_dir(stages_dir)?
+            .map_while(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.to_string_lossy().ends_with(".tar.xz"))
+            .filter_map(|p| {
+                // Prefer the timestamp embedded in the file name, falling back to the mtime
+                let created = p
+                    .file_name()
+                    .and_then(|f| parse_stagefile_timestamp(&f.to_string_lossy()))
+                    .or_else(|| p.metadata().and_then(|m| m.modified()).ok())?;
+                Some((p, now.duration_since(created).unwrap_or_default()))
+            })
+            .collect::<Vec<_>>();
+
+        // Newest first
+        stages.sort_by_key(|(_, age)| *age);
+
+        Ok(stages
+            .into_iter()
+            .skip(self.keep.unwrap_or(0))
+            .filter(|(_, age)| self.older_than.is_none_or(|d| *age > d))
+            .collect())
+    }
+}
+
+/// # Finds the sidecars that exist next to a stage file
+fn sidecars(stage: &Path) -> Vec<PathBuf> {
+    SIDECARS
+        .iter()
+        .map(|ext| {
+            let mut sidecar = OsString::from(stage);
+            sidecar.push(ext);
+            PathBuf::from(sidecar)
+        })
+        .filter(|p| p.is_file())
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prune_keeps_newest_and_ignores_sidecars() {
+        let dir = tempfile::tempdir().expect("Failed to create tempdir");
+        let stages = ["2025-01-01_00-00-00", "2025-02-01_00-00-00", "2025-03-01_00-00-00"].map(|t| dir.path().join(format!("lfstage-x-{t}.tar.xz")));
+        for stage in &stages {
+            fs::write(stage, "").expect("Failed to write stage");
+            fs::write(sidecars_of(stage, ".sha256"), "").expect("Failed to write sidecar");
+        }
+
+        let cmd = Cmd {
+            profile:    None,
+            keep:       Some(2),
+            older_than: None,
+            dry:        true,
+        };
+        let prunable = cmd.collect_prunable(dir.path(), SystemTime::now()).expect("Failed to collect stages");
+        let prunable = prunable.into_iter().map(|(p, _)| p).collect::<Vec<_>>();
+        assert_eq!(prunable, [stages[0].clone()]);
+        assert_eq!(sidecars(&stages[0]), [sidecars_of(&stages[0], ".sha256")]);
+    }
+
+    fn sidecars_of(stage: &Path, ext: &str) -> PathBuf {
+        let mut sidecar = OsString::from(stage);
+        sidecar.push(ext);
+        sidecar.into()
+    }
+}
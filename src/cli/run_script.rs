@@ -0,0 +1,149 @@
+// cli/run_script.rs
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+use std::{io, thread};
+
+use clap::Args;
+use fshelpers::mkdir_p;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use super::{CmdError, resolve_profile};
+use crate::config::CONFIG;
+use crate::exec;
+use crate::profile::Profile;
+use crate::utils::cmd::SCRIPT_LOG_DIR;
+
+/// How long to wait for an editor to finish saving before running the script again
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// The script to run
+    ///
+    /// Relative paths that don't exist are looked up in the profile's scripts directory, and then
+    /// matched against the start of the profile's build scripts, so `08` runs `08-gcc.sh`
+    pub script: PathBuf,
+
+    /// The profile whose environment to run the script in
+    ///
+    /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config
+    #[arg(short, long)]
+    pub profile: Option<String>,
+
+    /// Run the script again whenever it changes, until interrupted
+    #[arg(short, long)]
+    pub watch: bool,
+
+    /// Run the script again whenever anything in its directory changes
+    #[arg(long, requires = "watch")]
+    pub watch_dir: bool,
+}
+
+impl Cmd {
+    /// # Runs the run-script subcommand
+    ///
+    /// The run-script subcommand executes a single script in a profile's environment, the same way
+    /// build scripts are executed. With `--watch`, it's executed again on every save. Its output is
+    /// logged to `<script_log_dir>/<profile>/<script>.log`, like build scripts' output.
+    ///
+    /// # Errors
+    /// This function returns a `CmdError` if:
+    /// - The script does not exist.
+    /// - The log directory couldn't be created.
+    /// - The script failed, outside of watch mode.
+    /// - The script couldn't be watched.
+    pub fn run(&self) -> Result<(), CmdError> {
+        let profile = resolve_profile(self.profile.as_ref())?;
+        let script = self.resolve_script(&profile)?;
+
+        // Log the output like build scripts' output, so it can be compared
+        let log_dir = Path::new(&CONFIG.script_log_dir).join(profile.name());
+        mkdir_p(&log_dir)?;
+        SCRIPT_LOG_DIR.get_or_init(|| log_dir);
+
+        if !self.watch {
+            exec!(&profile; &script)?;
+            return Ok(())
+        }
+
+        self.watch(&profile, &script)
+    }
+
+    /// # Finds the script to run
+    ///
+    /// The path is taken as is if it exists, and otherwise looked up in the profile's scripts. If
+    /// there's no such script, it's taken as the start of the name of exactly one build script.
+    fn resolve_script(&self, profile: &Profile) -> Result<PathBuf, CmdError> {
+        let script = match self.script.exists() {
+            | true => self.script.clone(),
+            | false => profile.scripts_dir().join(&self.script),
+        };
+
+        if script.is_file() {
+            return Ok(script.canonicalize()?)
+        }
+
+        let prefix = self.script.to_string_lossy();
+        let mut matches = profile
+            .collect_build_scripts()
+            .into_iter()
+            .filter(|s| s.file_name().is_some_and(|n| n.to_string_lossy().starts_with(&*prefix)))
+            .collect::<Vec<_>>();
+
+        match matches.len() {
+            | 0 => {
+                error!("Script '{prefix}' does not exist");
+                Err(CmdError::MissingComponent(script))
+            },
+            | 1 => Ok(matches.remove(0).canonicalize()?),
+            | _ => {
+                let names = matches.iter().filter_map(|s| s.file_name()).map(|n| n.to_string_lossy().to_string()).collect();
+                Err(CmdError::AmbiguousScript(prefix.to_string(), names))
+            },
+        }
+    }
+
+    /// # Runs a script, then runs it again on every change until interrupted
+    fn watch(&self, profile: &Profile, script: &Path) -> Result<(), CmdError> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).map_err(io::Error::other)?;
+
+        // Editors often save by replacing the file, so its directory is watched either way
+        let dir = script.parent().unwrap_or_else(|| Path::new("/"));
+        watcher.watch(dir, RecursiveMode::NonRecursive).map_err(io::Error::other)?;
+        info!(
+            "Watching '{}' for changes, press Ctrl-C to stop",
+            if self.watch_dir { dir } else { script }.display()
+        );
+
+        loop {
+            println!("==> Running '{}'", script.display());
+            match exec!(&profile; script) {
+                | Ok(()) => println!("==> Succeeded"),
+                | Err(e) => println!("==> {e}"),
+            }
+            println!("{}", "-".repeat(80));
+
+            wait_for_change(&rx, script, self.watch_dir)?;
+        }
+    }
+}
+
+/// # Blocks until the script, or anything in its directory if `any` is set, changes
+///
+/// Events arriving shortly after the first are dropped, so a single save only causes a single run.
+fn wait_for_change(rx: &Receiver<notify::Result<Event>>, script: &Path, any: bool) -> io::Result<()> {
+    loop {
+        let event = rx.recv().map_err(io::Error::other)?.map_err(io::Error::other)?;
+        let changed = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_));
+        if changed && (any || event.paths.iter().any(|p| p == script)) {
+            break
+        }
+    }
+
+    thread::sleep(DEBOUNCE);
+    while rx.try_recv().is_ok() {}
+    Ok(())
+}
@@ -0,0 +1,74 @@
+// cli/selftest.rs
+
+use std::path::Path;
+
+use clap::Args;
+
+use super::CmdError;
+use crate::exec;
+
+/// The installed components lfstage can't run without
+const COMPONENTS: &[&str] = &[
+    "/usr/lib/lfstage/envs/internal.env",
+    "/usr/lib/lfstage/scripts/clean.sh",
+    "/usr/lib/lfstage/scripts/export.sh",
+    "/usr/lib/lfstage/scripts/import.sh",
+    "/usr/lib/lfstage/scripts/reqs.sh",
+    "/usr/lib/lfstage/scripts/save.sh",
+    "/usr/lib/lfstage/scripts/selftest.sh",
+];
+
+/// The known-good scripts executed to exercise the execution path
+const SCRIPTS: &[&str] = &["/usr/lib/lfstage/scripts/selftest.sh", "/usr/lib/lfstage/scripts/reqs.sh"];
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// Don't run the host requirements check
+    #[arg(long)]
+    pub assume_reqs: bool,
+}
+
+impl Cmd {
+    /// # Runs the selftest subcommand
+    ///
+    /// The selftest subcommand checks that lfstage is correctly installed and functional on this
+    /// host, independent of any profile. It checks that the installed components exist, then runs
+    /// the known-good scripts through the same execution path as build scripts.
+    ///
+    /// # Errors
+    /// This function returns a `CmdError` if any check failed.
+    pub fn run(&self) -> Result<(), CmdError> {
+        let mut failed = 0;
+
+        for component in COMPONENTS {
+            let ok = Path::new(component).exists();
+            report(ok, "exists", component);
+            failed += usize::from(!ok);
+        }
+
+        for script in SCRIPTS {
+            if self.assume_reqs && script.ends_with("/reqs.sh") {
+                println!("{:<8} {:<8} {script}", "skipped", "runs");
+                continue
+            }
+
+            // exec exits if the script doesn't exist, which was already reported above
+            let ok = Path::new(script).exists() && exec!(script).is_ok();
+            report(ok, "runs", script);
+            failed += usize::from(!ok);
+        }
+
+        if failed > 0 {
+            return Err(CmdError::SelftestFailed(failed));
+        }
+
+        info!("LFStage is correctly installed and functional");
+        Ok(())
+    }
+}
+
+/// # Prints the result of a single check
+fn report(ok: bool, check: &str, subject: &str) {
+    let status = if ok { "ok" } else { "FAILED" };
+    println!("{status:<8} {check:<8} {subject}");
+}
@@ -0,0 +1,124 @@
+// cli/sources.rs
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use clap::{Args, Subcommand};
+use tempfile::NamedTempFile;
+
+use super::{CmdError, resolve_profile};
+use crate::utils::dl::{add_source, remove_source};
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    #[command(subcommand)]
+    pub action: Action,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Action {
+    /// Add a source to a profile's sources list
+    Add {
+        /// The profile to add the source to
+        ///
+        /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config
+        #[arg(short, long)]
+        profile: Option<String>,
+
+        /// The URL of the source, optionally followed by mirrors separated by ' | '
+        url: String,
+
+        /// The file name to save the source as, defaulting to the file name of the URL
+        dest: Option<String>,
+
+        /// The SHA-256 digest of the source
+        #[arg(long, value_name = "DIGEST")]
+        sha256: Option<String>,
+    },
+
+    /// Remove a source from a profile's sources list
+    #[command(alias = "remove")]
+    Rm {
+        /// The profile to remove the source from
+        ///
+        /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config
+        #[arg(short, long)]
+        profile: Option<String>,
+
+        /// The destination of the source to remove
+        dest: String,
+    },
+}
+
+impl Cmd {
+    /// # Runs the sources subcommand
+    ///
+    /// The sources subcommand edits a profile's sources list, validating it with the same parser
+    /// used for downloads. Comments and other lines are left alone, and the list is replaced
+    /// atomically so it's never left half-written.
+    ///
+    /// # Errors
+    /// This function returns a `CmdError` if:
+    /// - The sources list is missing or could not be read or written.
+    /// - The sources list or the new source is invalid.
+    /// - The source to add is already listed, or the source to remove isn't.
+    pub fn run(&self) -> Result<(), CmdError> {
+        let (profile, edit) = match &self.action {
+            | Action::Add { profile, url, dest, sha256 } => {
+                let mut line = url.clone();
+                if let Some(sha256) = sha256 {
+                    line = format!("{line}#sha256={sha256}");
+                }
+                if let Some(dest) = dest {
+                    line = format!("{line} -> {dest}");
+                }
+                (profile, Edit::Add(line))
+            },
+            | Action::Rm { profile, dest } => (profile, Edit::Rm(dest)),
+        };
+
+        let profile = resolve_profile(profile.as_ref())?;
+        let sources_file = profile.sources_file();
+        if !sources_file.exists() {
+            error!("Sources list for profile '{profile}' does not exist");
+            return Err(CmdError::MissingComponent(sources_file));
+        }
+
+        let contents = fs::read_to_string(&sources_file)?;
+        match edit {
+            | Edit::Add(line) => {
+                write_atomically(&sources_file, &add_source(&contents, &line)?)?;
+                info!("Added '{line}' to the sources for '{profile}'");
+            },
+            | Edit::Rm(dest) => {
+                write_atomically(&sources_file, &remove_source(&contents, dest)?)?;
+                info!("Removed '{dest}' from the sources for '{profile}'");
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// # A change to a sources list
+enum Edit<'a> {
+    /// Append a line
+    Add(String),
+
+    /// Remove the source with a destination
+    Rm(&'a str),
+}
+
+/// # Replaces a file's contents without ever leaving it partially written
+///
+/// The contents are written to a temporary file next to it, which is then renamed over it. The
+/// file's permissions are kept, since sources lists are executed.
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut tmp = NamedTempFile::new_in(dir)?;
+    tmp.write_all(contents.as_bytes())?;
+    tmp.as_file().set_permissions(fs::metadata(path)?.permissions())?;
+    tmp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
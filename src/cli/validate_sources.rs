@@ -0,0 +1,55 @@
+// cli/validate_sources.rs
+
+use clap::Args;
+use futures::future::join_all;
+
+use super::{CmdError, resolve_profile};
+use crate::utils::dl::check_url;
+
+#[derive(Args, Debug)]
+pub struct Cmd {
+    /// The profile to validate the sources of
+    ///
+    /// Defaults to `LFSTAGE_PROFILE`, then `default_profile` from the config
+    pub profile: Option<String>,
+}
+
+impl Cmd {
+    /// # Runs the validate-sources subcommand
+    ///
+    /// The validate-sources subcommand checks that every source URL for a profile is reachable,
+    /// without downloading anything.
+    ///
+    /// # Errors
+    /// This function returns a `CmdError` if:
+    /// - The sources list is missing or could not be read.
+    /// - One or more sources failed validation.
+    pub async fn run(&self) -> Result<(), CmdError> {
+        let profile = resolve_profile(self.profile.as_ref())?;
+
+        if !profile.sources_file().exists() {
+            error!("Sources list for profile '{profile}' does not exist");
+            return Err(CmdError::MissingComponent(profile.sources_file()));
+        }
+
+        let dls = profile.read_dls()?;
+        info!("Validating {} sources for '{profile}'", dls.len());
+
+        let statuses = join_all(dls.iter().map(|dl| check_url(&dl.url))).await;
+
+        let mut failed = 0;
+        for (dl, status) in dls.iter().zip(statuses) {
+            if !status.is_ok() {
+                failed += 1;
+            }
+            println!("{status:<12} {}", dl.url);
+        }
+
+        if failed > 0 {
+            return Err(CmdError::InvalidSources(failed));
+        }
+
+        info!("All sources for '{profile}' are reachable");
+        Ok(())
+    }
+}
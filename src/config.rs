@@ -1,32 +1,236 @@
-use std::fs;
-use std::path::Path;
-use std::sync::LazyLock;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{LazyLock, OnceLock};
+use std::{env, fs};
 
-use serde::Deserialize;
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Serialize};
+use toml::de::ValueDeserializer;
+use tracing::metadata::LevelFilter;
 
-pub static CONFIG: LazyLock<Config> = LazyLock::new(Config::load);
+/// The config in effect, see [`EffectiveConfig`]
+pub static CONFIG: EffectiveConfig = EffectiveConfig;
 
-#[derive(Debug, Deserialize)]
+/// The system-wide config
+static GLOBAL_CONFIG: LazyLock<Config> = LazyLock::new(Config::load);
+
+/// The system-wide config with a profile's overrides, see [`apply_profile_overrides`]
+static PROFILE_CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The path the config is read from, if overridden with `--config`
+///
+/// This must be set before [`CONFIG`] is first accessed.
+pub static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+const DEFAULT_CONFIG_PATH: &str = "/etc/lfstage/config.toml";
+
+/// # The config in effect
+///
+/// This is the system-wide config, unless a profile's overrides have been applied, in which case
+/// it's the config with those overrides from then on.
+pub struct EffectiveConfig;
+
+impl Deref for EffectiveConfig {
+    type Target = Config;
+
+    #[inline]
+    fn deref(&self) -> &Config { PROFILE_CONFIG.get().unwrap_or_else(|| &GLOBAL_CONFIG) }
+}
+
+/// # Applies a profile's config overrides
+///
+/// The overrides are read from a file with the same keys as the system-wide config. Keys absent
+/// from it keep their system-wide values, and environment variables still take precedence. A
+/// missing file is fine, and an invalid one is warned about and ignored.
+pub fn apply_profile_overrides(path: &Path) {
+    let Ok(overrides) = fs::read_to_string(path) else { return };
+
+    match GLOBAL_CONFIG.with_overrides(&overrides) {
+        | Ok(config) => {
+            debug!("Applied config overrides from '{}'", path.display());
+            let _ = PROFILE_CONFIG.set(config);
+        },
+        | Err(e) => warn!("Ignoring invalid '{}': {e}", path.display()),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config {
-    pub jobs:      usize,
-    pub log_level: String,
-    pub strip:     bool,
+    pub build_umask:        Option<String>,
+    pub connect_timeout:    u64,
+    pub default_profile:    String,
+    pub download_retries:   usize,
+    pub http_version:       HttpVersion,
+    pub insecure_tls_hosts: Vec<String>,
+    pub jobs:               usize,
+    pub keyring:            Option<String>,
+    pub log_color:          LogColor,
+    pub log_keep:           usize,
+    pub log_format:         LogFormat,
+    pub log_level:          String,
+    pub log_max_size:       String,
+    pub log_path:           String,
+    pub max_download_rate:  Option<String>,
+    pub mount_sentinel:     Option<String>,
+    pub release_file:       String,
+    pub request_timeout:    u64,
+    pub require_checksum:   bool,
+    pub script_log_dir:     String,
+    pub script_timeout:     u64,
+    pub shell:              String,
+    pub signing_key:        Option<String>,
+    pub signing_tool:       SigningTool,
+    pub source_store:       bool,
+    pub strip:              bool,
+    pub strip_exclude:      Vec<String>,
+    pub user_agent_comment: Option<String>,
+    pub verify_signatures:  bool,
+}
+
+/// # The HTTP versions negotiated when downloading
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    /// Negotiate the version with the server
+    #[default]
+    Auto,
+
+    /// Only use HTTP/1
+    Http1,
+
+    /// Assume the server speaks HTTP/2
+    Http2,
+}
+
+/// # The tool stage files and exports are signed with
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningTool {
+    /// Sign with gpg, writing `<file>.sig`
+    #[default]
+    Gpg,
+
+    /// Sign with minisign, writing `<file>.minisig`
+    Minisign,
+}
+
+/// # When console logs are colored
+///
+/// The log file is never colored.
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogColor {
+    /// Color if stdout is a terminal
+    #[default]
+    Auto,
+
+    /// Always color
+    Always,
+
+    /// Never color
+    Never,
+}
+
+/// # The format of log lines
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Compact lines meant for humans
+    #[default]
+    Compact,
+
+    /// One JSON object per line, for log aggregators
+    Json,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            jobs:      num_cpus::get(),
-            log_level: "trace".to_string(),
-            strip:     true,
+            build_umask:        None,
+            connect_timeout:    120,
+            default_profile:    "x86_64-glibc-tox-stage2".to_string(),
+            download_retries:   2,
+            http_version:       HttpVersion::Auto,
+            insecure_tls_hosts: Vec::new(),
+            jobs:               num_cpus::get(),
+            keyring:            None,
+            log_color:          LogColor::Auto,
+            log_keep:           3,
+            log_format:         LogFormat::Compact,
+            log_level:          "trace".to_string(),
+            log_max_size:       "64 MiB".to_string(),
+            log_path:           "/var/log/lfstage/lfstage.log".to_string(),
+            max_download_rate:  None,
+            mount_sentinel:     None,
+            release_file:       "/etc/lfstage-release".to_string(),
+            request_timeout:    0,
+            require_checksum:   false,
+            script_log_dir:     "/var/log/lfstage".to_string(),
+            script_timeout:     0,
+            shell:              "bash".to_string(),
+            signing_key:        None,
+            signing_tool:       SigningTool::Gpg,
+            source_store:       false,
+            strip:              true,
+            strip_exclude:      Vec::new(),
+            user_agent_comment: None,
+            verify_signatures:  true,
         }
     }
 }
 
 impl Config {
+    /// # Loads the config
+    ///
+    /// Values are taken from `LFSTAGE_<KEY>` environment variables first, then from
+    /// the config file, then from the defaults.
     pub fn load() -> Self {
-        let config_path = Path::new("/etc/lfstage/config.toml");
+        let mut config = Self::load_file();
+        config.apply_env(env::vars_os());
+        config.validate();
+        config
+    }
+
+    /// # Overlays overrides, written like the config, onto a copy of this config
+    ///
+    /// Environment variables are applied again afterwards so they keep precedence.
+    fn with_overrides(&self, overrides: &str) -> Result<Self, toml::de::Error> {
+        let toml::Value::Table(mut merged) = toml::Value::try_from(self).map_err(de::Error::custom)? else {
+            return Err(de::Error::custom("config didn't serialize to a table"))
+        };
+        merged.extend(toml::from_str::<toml::Table>(overrides)?);
+
+        let mut config: Self = serde_ignored::deserialize(toml::Value::Table(merged), |path| {
+            warn!("Ignoring unknown key '{path}' in the profile's config");
+        })?;
+        config.apply_env(env::vars_os());
+        config.validate();
+        Ok(config)
+    }
+
+    /// # Replaces values that would misbehave later
+    ///
+    /// Zero jobs means one per CPU, so jobs is always at least one afterwards. An unknown log level
+    /// falls back to the default. This runs before logging is set up, so problems are printed.
+    fn validate(&mut self) {
+        if self.jobs == 0 {
+            self.jobs = num_cpus::get();
+        }
+
+        if LevelFilter::from_str(&self.log_level).is_err() {
+            let default = Self::default().log_level;
+            eprintln!("Invalid log_level '{}', falling back to '{default}'", self.log_level);
+            self.log_level = default;
+        }
+    }
+
+    fn load_file() -> Self {
+        let config_path = CONFIG_PATH.get().map_or_else(|| Path::new(DEFAULT_CONFIG_PATH), PathBuf::as_path);
 
         if !config_path.exists() {
             eprintln!("The config at '{}' does not exist.", config_path.display());
@@ -42,19 +246,151 @@ impl Config {
             | Ok(s) => s,
         };
 
-        let mut config = match toml::de::from_str(&config_str) {
+        // Unknown keys are most likely typos, so they're pointed out rather than silently ignored
+        let parsed = toml::Deserializer::parse(&config_str).and_then(|de| {
+            serde_ignored::deserialize(de, |path| {
+                eprintln!("Ignoring unknown key '{path}' in the config");
+            })
+        });
+
+        match parsed {
             | Err(e) => {
                 eprintln!("Invalid config: {e}");
                 eprintln!("Falling back to the default config.");
                 Self::default()
             },
             | Ok(c) => c,
-        };
+        }
+    }
+
+    /// # Overlays values from the environment
+    ///
+    /// Each key may be overridden by `LFSTAGE_<KEY>`, e.g. `LFSTAGE_JOBS=4`. Values are written as
+    /// in the config, though strings needn't be quoted. The variables are passed in, usually from
+    /// [`env::vars_os`], and those that aren't valid UTF-8 are ignored.
+    fn apply_env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, vars: impl IntoIterator<Item = (K, V)>) {
+        let vars = vars
+            .into_iter()
+            .filter_map(|(k, v)| Some((k.as_ref().to_str()?.to_owned(), v.as_ref().to_str()?.to_owned())))
+            .collect::<HashMap<_, _>>();
+
+        env_override(&vars, "build_umask", &mut self.build_umask);
+        env_override(&vars, "connect_timeout", &mut self.connect_timeout);
+        env_override(&vars, "default_profile", &mut self.default_profile);
+        env_override(&vars, "download_retries", &mut self.download_retries);
+        env_override(&vars, "http_version", &mut self.http_version);
+        env_override(&vars, "insecure_tls_hosts", &mut self.insecure_tls_hosts);
+        env_override(&vars, "jobs", &mut self.jobs);
+        env_override(&vars, "keyring", &mut self.keyring);
+        env_override(&vars, "log_color", &mut self.log_color);
+        env_override(&vars, "log_keep", &mut self.log_keep);
+        env_override(&vars, "log_format", &mut self.log_format);
+        env_override(&vars, "log_level", &mut self.log_level);
+        env_override(&vars, "log_max_size", &mut self.log_max_size);
+        env_override(&vars, "log_path", &mut self.log_path);
+        env_override(&vars, "max_download_rate", &mut self.max_download_rate);
+        env_override(&vars, "mount_sentinel", &mut self.mount_sentinel);
+        env_override(&vars, "release_file", &mut self.release_file);
+        env_override(&vars, "request_timeout", &mut self.request_timeout);
+        env_override(&vars, "require_checksum", &mut self.require_checksum);
+        env_override(&vars, "script_log_dir", &mut self.script_log_dir);
+        env_override(&vars, "script_timeout", &mut self.script_timeout);
+        env_override(&vars, "shell", &mut self.shell);
+        env_override(&vars, "signing_key", &mut self.signing_key);
+        env_override(&vars, "signing_tool", &mut self.signing_tool);
+        env_override(&vars, "source_store", &mut self.source_store);
+        env_override(&vars, "strip", &mut self.strip);
+        env_override(&vars, "strip_exclude", &mut self.strip_exclude);
+        env_override(&vars, "user_agent_comment", &mut self.user_agent_comment);
+        env_override(&vars, "verify_signatures", &mut self.verify_signatures);
+    }
 
-        if config.jobs == 0 {
-            config.jobs = num_cpus::get();
+    /// # Parses the configured umask for build scripts
+    ///
+    /// The umask is written in octal, e.g. "022". Returns `None` if it's unset or invalid, in which
+    /// case the umask is inherited.
+    pub fn build_umask(&self) -> Option<libc::mode_t> {
+        let umask = self.build_umask.as_deref()?;
+        match libc::mode_t::from_str_radix(umask, 8) {
+            | Ok(m) if m <= 0o777 => Some(m),
+            | _ => {
+                warn!("Invalid build_umask '{umask}', inheriting the umask instead");
+                None
+            },
         }
+    }
+}
 
-        config
+/// # Overrides a config value with `LFSTAGE_<KEY>` if it's among `vars`
+///
+/// The value is parsed as a TOML value, falling back to a string. If it doesn't parse as either,
+/// the existing value is kept.
+fn env_override<T: DeserializeOwned>(vars: &HashMap<String, String>, key: &str, value: &mut T) {
+    let var = format!("LFSTAGE_{}", key.to_uppercase());
+    let Some(raw) = vars.get(&var) else { return };
+
+    let parse = |raw: &str| ValueDeserializer::parse(raw).and_then(T::deserialize);
+    let quoted = toml::Value::String(raw.clone()).to_string();
+    match parse(raw).or_else(|e| parse(&quoted).map_err(|_| e)) {
+        | Ok(v) => *value = v,
+        | Err(e) => eprintln!("Ignoring invalid {var}='{raw}': {}", e.message()),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn env_overrides_config() {
+        let mut config = Config::default();
+        config.apply_env([
+            ("LFSTAGE_JOBS", "3"),
+            ("LFSTAGE_DEFAULT_PROFILE", "foo"),
+            ("LFSTAGE_KEYRING", "/etc/keyring.gpg"),
+            ("LFSTAGE_STRIP_EXCLUDE", r#"["usr/lib"]"#),
+            ("LFSTAGE_CONNECT_TIMEOUT", "soon"),
+            ("LFSTAGE_SHELL", "zsh"),
+            ("SHELL", "fish"),
+        ]);
+
+        assert_eq!(config.jobs, 3);
+        assert_eq!(config.default_profile, "foo");
+        assert_eq!(config.keyring.as_deref(), Some("/etc/keyring.gpg"));
+        assert_eq!(config.strip_exclude, ["usr/lib"]);
+        assert_eq!(config.connect_timeout, 120);
+        assert_eq!(config.shell, "zsh");
+    }
+
+    #[test]
+    fn overrides_keep_absent_keys() {
+        // These keys aren't set in the environment by any other test
+        let base = Config {
+            download_retries: 8,
+            strip: false,
+            ..Config::default()
+        };
+        let config = base
+            .with_overrides("download_retries = 2\nuser_agent_comment = \"admin@example.com\"")
+            .expect("overrides should parse");
+
+        assert_eq!(config.download_retries, 2);
+        assert_eq!(config.user_agent_comment.as_deref(), Some("admin@example.com"));
+        assert!(!config.strip);
+        assert!(base.with_overrides("download_retries = \"many\"").is_err());
+    }
+
+    #[test]
+    fn validate_replaces_bad_values() {
+        let mut config = Config {
+            jobs: 0,
+            log_level: "loud".to_string(),
+            ..Config::default()
+        };
+        config.validate();
+
+        assert!(config.jobs >= 1);
+        assert_eq!(config.log_level, Config::default().log_level);
     }
 }
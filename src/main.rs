@@ -5,17 +5,30 @@ mod config;
 mod profile;
 mod utils;
 
-use std::process::exit;
-
 use clap::Parser;
 
+use crate::utils::init::exit;
+
 #[macro_use] extern crate tracing;
 
 #[tokio::main]
 async fn main() {
-    utils::init::init();
-    if let Err(e) = cli::Cli::parse().run().await {
+    let cli = cli::Cli::parse();
+    if cli.print_completions() {
+        return
+    }
+
+    if let Some(path) = &cli.config {
+        let _ = config::CONFIG_PATH.set(path.clone());
+    }
+
+    utils::init::init(cli.no_color, cli.needs_root());
+    utils::init::handle_signals();
+    if let Err(e) = cli.run().await {
         error!("{e}");
         exit(1);
     }
+
+    // Statics aren't dropped on return from main either, so the log is flushed explicitly
+    exit(0)
 }
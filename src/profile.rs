@@ -1,20 +1,72 @@
 // profile.rs
 //! The profile struct and related code
 
-use std::ffi::OsStr;
+use std::borrow::Borrow;
+use std::cmp::Reverse;
+use std::fmt::Write as _;
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
-use std::process::exit;
-use std::{fmt, fs, ptr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use std::{fmt, fs, io, ptr, thread};
 
 use fshelpers::mkdir_p;
 use is_executable::IsExecutable;
+use serde::Deserialize;
 
+use crate::config::CONFIG;
 use crate::exec;
+use crate::utils::cas;
+use crate::utils::cmd::{ScriptError, jobs};
+use crate::utils::dl::sha256_file;
+use crate::utils::init::exit;
+
+/// The build root, exported to scripts as `$LFS`
+pub const MOUNT_DIR: &str = "/var/lib/lfstage/mount";
+
+/// # Per-profile settings, read from `profile.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ProfileConfig {
+    /// Defaults for the build command's flags
+    pub build: BuildDefaults,
+}
+
+/// # Defaults for the build command's flags
+///
+/// Flags given on the command line take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct BuildDefaults {
+    /// Assume the system meets requirements, like `--assume-reqs`
+    pub assume_reqs: bool,
+
+    /// Run scripts in the same group concurrently, like `--concurrent-scripts`
+    pub concurrent_scripts: bool,
+
+    /// The number of jobs, like `--jobs`
+    pub jobs: Option<usize>,
+
+    /// Retry a failed build script this many times, like `--retry-failed-scripts`
+    pub retry_failed_scripts: Option<usize>,
 
+    /// Show an aggregate progress bar for downloads, like `--show-total`
+    pub show_total: bool,
+
+    /// Don't strip binaries, like `--skip-strip`
+    pub skip_strip: bool,
+}
+
+/// # A profile
+///
+/// Installed profiles are referred to by name. A profile anywhere else on disk is referred to by
+/// the absolute path of its directory, see [`ProfileBuf::from_dir`].
 #[derive(Debug)]
 #[repr(transparent)]
 pub struct Profile {
-    pub name: str,
+    inner: str,
 }
 
 impl AsRef<Self> for Profile {
@@ -24,7 +76,7 @@ impl AsRef<Self> for Profile {
 
 impl AsRef<str> for Profile {
     #[inline]
-    fn as_ref(&self) -> &str { &self.name }
+    fn as_ref(&self) -> &str { &self.inner }
 }
 
 impl AsRef<Profile> for str {
@@ -34,18 +86,106 @@ impl AsRef<Profile> for str {
 
 impl fmt::Display for Profile {
     #[inline]
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", &self.name) }
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.name()) }
+}
+
+impl ToOwned for Profile {
+    type Owned = ProfileBuf;
+
+    #[inline]
+    fn to_owned(&self) -> ProfileBuf { ProfileBuf(self.inner.to_owned()) }
+}
+
+/// # An owned [`Profile`]
+///
+/// [`Profile`] is to [`ProfileBuf`] what [`Path`] is to [`PathBuf`]. It derefs to a [`Profile`], so
+/// it can be held by value without keeping the name alive elsewhere.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProfileBuf(String);
+
+impl ProfileBuf {
+    #[inline]
+    pub fn new<S: Into<String>>(name: S) -> Self { Self(name.into()) }
+
+    /// # Roots a profile at a directory anywhere on disk
+    ///
+    /// The directory must contain what would otherwise be installed under
+    /// `/var/lib/lfstage/profiles`. The profile is named after the directory, and its sources and
+    /// stage files are cached in `.cache` within it, so it doesn't collide with an installed
+    /// profile of the same name.
+    pub fn from_dir(dir: &Path) -> io::Result<Self> {
+        let dir = dir.canonicalize()?;
+        if !dir.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotADirectory, format!("'{}' is not a directory", dir.display())));
+        }
+
+        let path = dir
+            .to_str()
+            .filter(|_| dir.file_name().is_some())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a valid profile name", dir.display())))?;
+
+        info!("Using profile '{}' from '{path}'", Profile::new(path));
+        Ok(Self(path.to_string()))
+    }
+}
+
+impl Deref for ProfileBuf {
+    type Target = Profile;
+
+    #[inline]
+    fn deref(&self) -> &Profile { Profile::new(&self.0) }
+}
+
+impl Borrow<Profile> for ProfileBuf {
+    #[inline]
+    fn borrow(&self) -> &Profile { self }
+}
+
+impl AsRef<Profile> for ProfileBuf {
+    #[inline]
+    fn as_ref(&self) -> &Profile { self }
+}
+
+impl AsRef<str> for ProfileBuf {
+    #[inline]
+    fn as_ref(&self) -> &str { &self.0 }
+}
+
+impl From<String> for ProfileBuf {
+    #[inline]
+    fn from(name: String) -> Self { Self(name) }
+}
+
+impl From<&Profile> for ProfileBuf {
+    #[inline]
+    fn from(profile: &Profile) -> Self { profile.to_owned() }
+}
+
+impl fmt::Display for ProfileBuf {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { fmt::Display::fmt(&**self, f) }
 }
 
 impl Profile {
     #[inline]
     pub fn new<S: AsRef<str> + ?Sized>(s: &S) -> &Self {
-        // SAFETY: Trust me bro
+        // SAFETY: Profile is a repr(transparent) wrapper around str, so the pointer cast keeps the
+        // same layout and the lifetime of s
         unsafe { &*(ptr::from_ref(s.as_ref()) as *const Self) }
     }
 
+    /// # Returns the name of the profile
+    ///
+    /// For a profile rooted at a directory, this is the name of the directory.
     #[inline]
-    pub fn tmp_dir(&self) -> PathBuf { Path::new("/tmp/lfstage").join(&self.name) }
+    pub fn name(&self) -> &str { self.dir().and_then(Path::file_name).and_then(|n| n.to_str()).unwrap_or(&self.inner) }
+
+    /// # Returns the directory the profile is rooted at, if it isn't an installed profile
+    #[inline]
+    fn dir(&self) -> Option<&Path> { Some(Path::new(&self.inner)).filter(|p| p.is_absolute()) }
+
+    #[inline]
+    pub fn tmp_dir(&self) -> PathBuf { Path::new("/tmp/lfstage").join(self.name()) }
 
     #[inline]
     pub fn stagefilename_file(&self) -> PathBuf { self.tmp_dir().join("stagefilename") }
@@ -54,10 +194,19 @@ impl Profile {
     pub fn timestamp_file(&self) -> PathBuf { self.tmp_dir().join("timestamp") }
 
     #[inline]
-    pub fn profile_lib_dir(&self) -> PathBuf { Path::new("/var/lib/lfstage/profiles").join(&self.name) }
+    pub fn strip_file(&self) -> PathBuf { self.tmp_dir().join("strip") }
+
+    #[inline]
+    pub fn profile_lib_dir(&self) -> PathBuf {
+        self.dir()
+            .map_or_else(|| Path::new("/var/lib/lfstage/profiles").join(self.name()), Path::to_path_buf)
+    }
 
     #[inline]
-    pub fn profile_cache_dir(&self) -> PathBuf { Path::new("/var/cache/lfstage/profiles").join(&self.name) }
+    pub fn profile_cache_dir(&self) -> PathBuf {
+        self.dir()
+            .map_or_else(|| Path::new("/var/cache/lfstage/profiles").join(self.name()), |d| d.join(".cache"))
+    }
 
     #[inline]
     pub fn envs_dir(&self) -> PathBuf { self.profile_lib_dir().join("envs") }
@@ -74,6 +223,38 @@ impl Profile {
     #[inline]
     pub fn sources_file(&self) -> PathBuf { self.profile_lib_dir().join("sources") }
 
+    #[inline]
+    pub fn config_file(&self) -> PathBuf { self.profile_lib_dir().join("profile.toml") }
+
+    #[inline]
+    pub fn config_overrides_file(&self) -> PathBuf { self.profile_lib_dir().join("config.toml") }
+
+    #[inline]
+    pub fn failed_sources_file(&self) -> PathBuf { self.profile_cache_dir().join("failed-sources") }
+
+    #[inline]
+    pub fn source_urls_file(&self) -> PathBuf { self.profile_cache_dir().join("source-urls.json") }
+
+    #[inline]
+    pub fn source_etags_file(&self) -> PathBuf { self.profile_cache_dir().join("source-etags.json") }
+
+    #[inline]
+    pub fn sources_lock_file(&self) -> PathBuf { self.profile_cache_dir().join("sources.lock") }
+
+    /// # Reads the profile's settings
+    ///
+    /// A missing `profile.toml` yields the defaults. An invalid one is warned about and ignored.
+    pub fn config(&self) -> ProfileConfig {
+        let Ok(config_str) = fs::read_to_string(self.config_file()) else {
+            return ProfileConfig::default()
+        };
+
+        toml::de::from_str(&config_str).unwrap_or_else(|e| {
+            warn!("Ignoring invalid '{}': {e}", self.config_file().display());
+            ProfileConfig::default()
+        })
+    }
+
     pub fn collect_build_scripts(&self) -> Vec<PathBuf> {
         // Gather all profile-specific scripts
         let mut scripts = self
@@ -92,86 +273,317 @@ impl Profile {
                 },
             })
             .map(|e| e.path())
-            .filter(|p| !p.is_dir() && p.is_executable())
-            .filter_map(|p| {
-                let str = p
-                    .file_name()
-                    .unwrap_or_else(|| {
-                        warn!("Path {p:?} has no file name!");
-                        warn!("Ignoring it");
-                        OsStr::new("") // kinda hacky but wtv
-                    })
-                    .to_string_lossy();
-                if str.chars().take(2).all(|c| c.is_ascii_digit()) { Some(p) } else { None }
-            })
+            .filter(|p| !p.is_dir() && p.is_executable() && is_numbered(p))
             .collect::<Vec<_>>();
 
-        // Sort them
-        scripts.sort_by_key(|p| {
-            p.file_name()
-                .and_then(|s| s.to_str())
-                .and_then(|s| s.split_once('-'))
-                .and_then(|(prefix, _)| prefix.parse::<u32>().ok())
-        });
-
+        sort_scripts(&mut scripts);
         scripts
     }
 
-    pub fn run_build_scripts(&self) {
-        for script in self.collect_build_scripts() {
-            info!("Running build script {}", script.display());
-            if let Err(e) = exec!(&self; &script) {
-                error!("Failure in {}: {e}", script.display());
+    /// # Runs the build scripts in order
+    ///
+    /// A failed script is retried up to `retries` times before giving up. Retrying is only safe for
+    /// idempotent scripts, so it's off by default.
+    ///
+    /// If `concurrent` is set, scripts in the same group, see [`script_group`], are run at the same
+    /// time, up to the number of jobs at once. Each group finishes before the next starts, and if
+    /// any script in a group fails, every failure is reported once the group is done.
+    ///
+    /// How long each script took is logged once they're done, or once one fails.
+    pub fn run_build_scripts(&self, retries: usize, concurrent: bool, env: &[(String, String)]) {
+        let scripts = self.collect_build_scripts();
+        let groups = match concurrent {
+            | true => scripts.chunk_by(|a, b| script_group(a) == script_group(b)).collect::<Vec<_>>(),
+            | false => scripts.chunks(1).collect(),
+        };
+
+        let mut durations = Vec::new();
+        for group in groups {
+            let mut failed = false;
+            for (script, duration, result) in self.run_script_group(group, retries, env) {
+                if let Err(e) = result {
+                    error!("Failure in {}: {e}", script.display());
+                    failed = true;
+                }
+                durations.push((script, duration));
+            }
+
+            if failed {
+                log_durations(&mut durations);
                 exit(1)
             }
         }
+
+        log_durations(&mut durations);
     }
 
-    pub fn setup_sources(&self) -> std::io::Result<()> {
-        let registered = self.get_registered_sources();
+    /// # Runs a group of build scripts concurrently, up to the number of jobs at once
+    ///
+    /// Every script in the group is run, even if another fails. Output is tagged with the name of
+    /// the script it came from, and each script still gets its own log. Returns how long each script
+    /// took and whether it succeeded.
+    fn run_script_group<'a>(&self, group: &'a [PathBuf], retries: usize, env: &[(String, String)]) -> Vec<(&'a Path, Duration, Result<(), ScriptError>)> {
+        let run = |script: &'a PathBuf| {
+            let start = Instant::now();
+            let result = self.run_build_script(script, retries, env);
+            (script.as_path(), start.elapsed(), result)
+        };
+
+        if let [script] = group {
+            return vec![run(script)]
+        }
 
-        let sources = self
-            .sources_dir()
-            .read_dir()?
-            .filter_map(|e| match e {
-                | Ok(e) => Some(e),
+        info!("Running {} build scripts concurrently", group.len());
+        let next = AtomicUsize::new(0);
+        let outcomes = Mutex::new(Vec::new());
+
+        thread::scope(|s| {
+            for _ in 0..jobs().clamp(1, group.len()) {
+                s.spawn(|| {
+                    while let Some(script) = group.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let name = script.file_name().unwrap_or_default().to_string_lossy();
+                        let _span = info_span!("script", %name).entered();
+
+                        let outcome = run(script);
+                        outcomes.lock().unwrap_or_else(PoisonError::into_inner).push(outcome);
+                    }
+                });
+            }
+        });
+
+        outcomes.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// # Runs a build script, retrying it up to `retries` times
+    fn run_build_script(&self, script: &Path, retries: usize, env: &[(String, String)]) -> Result<(), ScriptError> {
+        info!("Running build script {}", script.display());
+        for attempt in 0..retries {
+            match exec!(&self; script; env) {
+                | Ok(()) => return Ok(()),
                 | Err(e) => {
-                    warn!("Entry could not be accessed: {e}");
-                    warn!("Ignoring it");
-                    None
+                    warn!("Failure in {}: {e}", script.display());
+                    warn!("Retrying (attempt {} of {})", attempt + 1, retries);
                 },
-            })
-            .map(|e| e.path())
-            .filter(|p| registered.contains(&p.file_name().unwrap_or_default().to_string_lossy().to_string()))
-            .collect::<Vec<_>>();
+            }
+        }
+
+        exec!(&self; script; env)
+    }
+
+    /// # Copies the registered sources into the build root
+    ///
+    /// If `only` is given, sources not in it are left out.
+    pub fn setup_sources(&self, only: Option<&[String]>) -> std::io::Result<()> {
+        check_mount()?;
+        let registered = self.get_registered_sources(only);
+
+        // Destinations may be nested, e.g. `patches/foo.patch`, so they're looked up by path
+        let sources_dir = self.sources_dir();
+        let sources = registered.iter().filter(|dest| sources_dir.join(dest).exists()).collect::<Vec<_>>();
 
         debug!("Found registered sources: {sources:#?}");
 
-        let lfs_sources = Path::new("/var/lib/lfstage/mount/sources");
-        mkdir_p(lfs_sources)?;
+        let lfs_sources = Path::new(MOUNT_DIR).join("sources");
+        mkdir_p(&lfs_sources)?;
 
-        for source in sources {
-            let Some(source_filename) = source.file_name() else {
-                error!("Invalid source: {}", source.display());
-                exit(1);
-            };
+        for name in sources {
+            let source = sources_dir.join(name);
+            let dest = lfs_sources.join(name);
+            if let Some(parent) = dest.parent() {
+                mkdir_p(parent)?;
+            }
 
-            let dest = lfs_sources.join(source_filename);
-            fs::copy(source, dest)?;
+            // Sources from the store are reflinked where possible, directories are always copied
+            if CONFIG.source_store && cas::reflink(&source, &dest).is_ok() {
+                continue
+            }
+            copy_source(&source, &dest)?;
         }
 
         Ok(())
     }
 
-    pub fn save_stagefile(&self) -> std::io::Result<()> {
+    /// # Records how the stage was built in its release file
+    ///
+    /// The file is written under the build root at `release_file` from the config, as `KEY=VALUE`
+    /// lines so it's easy to grep. Each build script gets its own `SCRIPT` line.
+    pub fn write_release_file(&self, timestamp: &str) -> io::Result<()> {
+        let path = Path::new(MOUNT_DIR).join(CONFIG.release_file.trim_start_matches('/'));
+        if let Some(parent) = path.parent() {
+            mkdir_p(parent)?;
+        }
+
+        let mut release = format!("PROFILE={self}\nLFSTAGE_VERSION={}\nTIMESTAMP={timestamp}\n", env!("CARGO_PKG_VERSION"));
+        for script in self.collect_build_scripts() {
+            let name = script.file_name().unwrap_or(script.as_os_str()).to_string_lossy();
+            let _ = writeln!(release, "SCRIPT={name}");
+        }
+
+        fs::write(&path, release)?;
+        debug!("Wrote release file '{}'", path.display());
+        Ok(())
+    }
+
+    /// # Saves the stage file, returning its path
+    ///
+    /// Unless `checksum` is unset, its digest is written to a `.sha256` sidecar next to it, in the
+    /// format `sha256sum -c` reads.
+    pub fn save_stagefile(&self, checksum: bool) -> std::io::Result<PathBuf> {
         mkdir_p(self.stages_dir())?;
         if exec!(&self; "/usr/lib/lfstage/scripts/save.sh").is_err() {
             error!("Failed to save stage file");
             exit(1)
         }
 
-        info!("Saved stage file to {}", fs::read_to_string(self.stagefilename_file())?);
+        let stagefile = PathBuf::from(fs::read_to_string(self.stagefilename_file())?);
+        if !checksum {
+            info!("Saved stage file to {}", stagefile.display());
+            return Ok(stagefile)
+        }
 
-        Ok(())
+        let sha256 = sha256_file(&stagefile)?;
+        let mut sidecar = stagefile.clone().into_os_string();
+        sidecar.push(".sha256");
+        let name = stagefile.file_name().unwrap_or(stagefile.as_os_str()).to_string_lossy();
+        fs::write(&sidecar, format!("{sha256}  {name}\n"))?;
+        info!("Saved stage file to {} (sha256 {sha256})", stagefile.display());
+
+        Ok(stagefile)
+    }
+}
+
+/// # Copies a source into the build root
+///
+/// Repositories are copied recursively, keeping symlinks as they are.
+fn copy_source(source: &Path, dest: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(source)?;
+    if meta.is_symlink() {
+        return std::os::unix::fs::symlink(fs::read_link(source)?, dest)
+    }
+
+    if !meta.is_dir() {
+        return fs::copy(source, dest).map(|_| ())
+    }
+
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        copy_source(&entry.path(), &dest.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+/// # Logs how long each build script took, slowest first
+fn log_durations(durations: &mut [(&Path, Duration)]) {
+    if durations.is_empty() {
+        return
+    }
+
+    durations.sort_by_key(|&(_, duration)| Reverse(duration));
+    info!("Build script durations:");
+    for (script, duration) in durations.iter() {
+        let name = script.file_name().unwrap_or(script.as_os_str()).to_string_lossy();
+        info!("  {:>9.1}s  {name}", duration.as_secs_f64());
+    }
+}
+
+/// # Whether a script's name starts with a number of at least two digits, like `05-setup.sh`
+///
+/// The number may be any length, so profiles with more than 100 scripts can use `100-foo.sh`.
+fn is_numbered(script: &Path) -> bool {
+    let name = script.file_name().unwrap_or_default().to_string_lossy();
+    name.chars().take_while(char::is_ascii_digit).count() >= 2
+}
+
+/// # Sorts build scripts by group, then by name
+///
+/// Groups are compared as numbers, so `100-foo.sh` runs after `99-bar.sh`.
+fn sort_scripts(scripts: &mut [PathBuf]) { scripts.sort_by(|a, b| script_group(a).cmp(&script_group(b)).then_with(|| a.cmp(b))); }
+
+/// # Finds the group of a build script, given by the digits its name starts with
+///
+/// Scripts sharing a group, such as `05a-zlib` and `05b-xz`, don't depend on each other and may run
+/// concurrently with `--concurrent-scripts`.
+fn script_group(script: &Path) -> Option<u32> {
+    let name = script.file_name()?.to_str()?;
+    let end = name.find(|c: char| !c.is_ascii_digit()).unwrap_or(name.len());
+    name[..end].parse().ok()
+}
+
+/// # Checks that the build root is prepared
+///
+/// The mount directory must exist and be a directory. If `mount_sentinel` is configured, that path
+/// relative to the mount directory must exist too, which catches a filesystem that was expected to
+/// be mounted there but isn't.
+fn check_mount() -> std::io::Result<()> {
+    let mount = Path::new(MOUNT_DIR);
+    if !mount.is_dir() {
+        error!("Build root '{MOUNT_DIR}' does not exist or is not a directory");
+        error!("Create it or reinstall lfstage, then run `lfstage clean`");
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("Missing build root '{MOUNT_DIR}'")));
+    }
+
+    if let Some(sentinel) = &CONFIG.mount_sentinel {
+        let sentinel = mount.join(sentinel);
+        if !sentinel.exists() {
+            error!("Build root sentinel '{}' does not exist", sentinel.display());
+            error!("Ensure the build root is set up before building");
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Missing build root sentinel '{}'", sentinel.display()),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{Profile, ProfileBuf, is_numbered, sort_scripts};
+
+    #[test]
+    fn profile_buf_derefs_to_profile() {
+        let owned = ProfileBuf::new("testing");
+        let profile: &Profile = &owned;
+        assert_eq!(profile.name(), "testing");
+        assert_eq!(profile.stages_dir(), Profile::new("testing").stages_dir());
+        assert_eq!(profile.to_owned(), owned);
+        assert_eq!(owned.to_string(), "testing");
+    }
+
+    #[test]
+    fn profile_buf_from_dir() {
+        let dir = tempfile::tempdir().expect("Failed to create tempdir");
+        let path = dir.path().canonicalize().expect("Failed to canonicalize tempdir");
+        let name = path.file_name().expect("Tempdir has no name").to_string_lossy().to_string();
+
+        let profile = ProfileBuf::from_dir(dir.path()).expect("Failed to root profile");
+        assert_eq!(profile.name(), name);
+        assert_eq!(profile.to_string(), name);
+        assert_eq!(profile.profile_lib_dir(), path);
+        assert_eq!(profile.sources_dir(), path.join(".cache/sources"));
+        assert_eq!(profile.tmp_dir(), Path::new("/tmp/lfstage").join(&name));
+
+        // Installed profiles are unaffected
+        assert_eq!(Profile::new("testing").profile_lib_dir(), Path::new("/var/lib/lfstage/profiles/testing"));
+    }
+
+    #[test]
+    fn scripts_sort_by_number() {
+        let mut scripts = ["100-foo.sh", "10-bar.sh", "99-baz.sh", "05b-xz.sh", "05a-zlib.sh"].map(PathBuf::from);
+        sort_scripts(&mut scripts);
+        assert_eq!(scripts, ["05a-zlib.sh", "05b-xz.sh", "10-bar.sh", "99-baz.sh", "100-foo.sh"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn scripts_need_a_number() {
+        assert!(is_numbered(Path::new("/p/scripts/05-setup.sh")));
+        assert!(is_numbered(Path::new("/p/scripts/100-foo.sh")));
+        assert!(!is_numbered(Path::new("/p/scripts/5-foo.sh")));
+        assert!(!is_numbered(Path::new("/p/scripts/setup.sh")));
     }
 }
@@ -0,0 +1,111 @@
+// utils/cas.rs
+//! The content-addressed source store
+//!
+//! When `source_store` is enabled, sources with a checksum are stored once under their digest and
+//! reflinked into each profile's sources directory, falling back to a copy on filesystems without
+//! reflinks. Identical content is then never downloaded twice, even under different file names,
+//! and on filesystems like btrfs or XFS, profiles sharing tarballs share the disk space too.
+//!
+//! Entries are never hardlinked out of the store, since patching a source in place would then
+//! corrupt the entry and every other profile's copy along with it.
+
+use std::fs::{self, File, Permissions};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use fshelpers::mkdir_p;
+
+use crate::config::CONFIG;
+use crate::utils::dl::sha256_file;
+
+/// The directory the store lives in
+pub const CAS_DIR: &str = "/var/cache/lfstage/cas";
+
+/// # Returns the path of the store entry for a digest
+#[inline]
+pub fn entry(sha256: &str) -> PathBuf { Path::new(CAS_DIR).join(sha256) }
+
+/// # Looks up the store entry for a digest
+///
+/// Returns `None` if the store is disabled or has no such entry. Entries are verified before
+/// they're returned, and one that no longer matches its digest is removed so the source is
+/// downloaded again.
+pub fn get(sha256: &str) -> Option<PathBuf> {
+    if !CONFIG.source_store {
+        return None
+    }
+
+    let entry = entry(sha256);
+    if !entry.is_file() {
+        return None
+    }
+
+    match sha256_file(&entry) {
+        | Ok(actual) if actual == sha256 => Some(entry),
+        | Ok(actual) => {
+            warn!("Store entry '{}' is corrupt (sha256 {actual}), removing it", entry.display());
+            fs::remove_file(&entry).ok();
+            None
+        },
+        | Err(e) => {
+            warn!("Failed to verify store entry '{}': {e}", entry.display());
+            None
+        },
+    }
+}
+
+/// # Moves a verified file into the store and reflinks it back into place at `dest`
+///
+/// The entry is made read-only to guard against accidental edits.
+pub fn store(file: &Path, sha256: &str, dest: &Path) -> std::io::Result<()> {
+    mkdir_p(CAS_DIR)?;
+    let entry = entry(sha256);
+    fs::rename(file, &entry)?;
+    fs::set_permissions(&entry, Permissions::from_mode(0o444))?;
+    reflink(&entry, dest)
+}
+
+/// # Reflinks a file to `dest`, replacing whatever is there
+///
+/// Falls back to a copy if the filesystem doesn't support reflinks. Either way, `dest` is a file
+/// of its own that can be changed without affecting `src`. It's writable even if `src` isn't.
+pub fn reflink(src: &Path, dest: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(dest).is_ok() {
+        fs::remove_file(dest)?;
+    }
+
+    let from = File::open(src)?;
+    let to = File::create(dest)?;
+
+    // SAFETY: Both file descriptors are open for the duration of the call
+    let cloned = unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE, from.as_raw_fd()) } == 0;
+    if !cloned {
+        drop(to);
+        fs::copy(src, dest)?;
+    }
+
+    fs::set_permissions(dest, Permissions::from_mode(0o644))
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reflinked_copies_are_independent() {
+        let dir = tempfile::tempdir().expect("Failed to create tempdir");
+        let src = dir.path().join("entry");
+        let dest = dir.path().join("source.tar.xz");
+        fs::write(&src, "original").expect("Failed to write entry");
+        fs::set_permissions(&src, Permissions::from_mode(0o444)).expect("Failed to make entry read-only");
+        fs::write(&dest, "stale").expect("Failed to write source");
+
+        reflink(&src, &dest).expect("Failed to reflink");
+        assert_eq!(fs::read_to_string(&dest).expect("Failed to read source"), "original");
+
+        fs::write(&dest, "patched").expect("Failed to patch source");
+        assert_eq!(fs::read_to_string(&src).expect("Failed to read entry"), "original");
+    }
+}
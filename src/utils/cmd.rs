@@ -1,20 +1,92 @@
 #![allow(clippy::expect_used)]
 
+use std::ffi::OsStr;
 use std::fs::{self, File};
 use std::io::{self, BufRead, Write};
-use std::path::Path;
-use std::process::{Command, Stdio, exit};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
 use std::thread;
+use std::time::{Duration, Instant};
+
+use fshelpers::mkdir_p;
+use tempfile::NamedTempFile;
+use thiserror::Error;
 
 use crate::config::CONFIG;
 use crate::profile::Profile;
+use crate::utils::init::exit;
+
+/// Whether to keep the environment file of each execution for debugging, see `--keep-env-file`
+pub static KEEP_ENV_FILE: AtomicBool = AtomicBool::new(false);
+
+/// The directory each script's output is logged to, see `--log-dir`
+pub static SCRIPT_LOG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// The number of jobs for this invocation, if it overrides `jobs` from the config
+pub static JOBS: OnceLock<usize> = OnceLock::new();
+
+/// # Returns the number of jobs scripts are told to use
+#[inline]
+pub fn jobs() -> usize { JOBS.get().copied().unwrap_or(CONFIG.jobs) }
+
+/// The process groups of the scripts currently running, see [`stop_scripts`]
+static RUNNING: Mutex<Vec<libc::pid_t>> = Mutex::new(Vec::new());
+
+/// Whether lfstage is stopping, in which case no more scripts are started
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// How long scripts get to exit after being signaled before they're killed
+const STOP_GRACE: Duration = Duration::from_secs(5);
+
+/// How long a script may run for this invocation, if it overrides `script_timeout` from the config
+pub static SCRIPT_TIMEOUT: OnceLock<u64> = OnceLock::new();
+
+/// # Returns how long a script may run before it's killed, or `None` if it may run forever
+#[inline]
+pub fn script_timeout() -> Option<Duration> {
+    let secs = SCRIPT_TIMEOUT.get().copied().unwrap_or(CONFIG.script_timeout);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+#[derive(Debug, Error)]
+pub enum ScriptError {
+    #[error("The shell '{0}' does not exist, set `shell` in the config to an installed shell")]
+    MissingShell(String),
 
-// TODO: Create a thiserror for script failures prolly
+    #[error("Failed to spawn '{path}': {source}")]
+    Spawn {
+        path:   PathBuf,
+        source: io::Error,
+    },
+
+    #[error("'{path}' failed: {status}")]
+    Failed {
+        path:   PathBuf,
+        status: ExitStatus,
+    },
+
+    #[error("'{path}' timed out after {}s", timeout.as_secs())]
+    Timeout {
+        path:    PathBuf,
+        timeout: Duration,
+    },
+
+    #[error("Not running '{0}' since lfstage is stopping")]
+    Stopping(PathBuf),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
 
-// This could be written to take environment variables as vector argument but I cba
 /// # WARN: MUST CALL A SCRIPT, NOT A COMMAND
-#[allow(clippy::panic)]
-pub fn exec<R, P>(profile: Option<R>, script: P) -> io::Result<()>
+///
+/// `env` is exported after the profile's environment, so it overrides it.
+#[allow(clippy::panic, clippy::needless_pass_by_value)]
+pub fn exec<R, P>(profile: Option<R>, script: P, env: &[(String, String)]) -> Result<(), ScriptError>
 where
     R: AsRef<Profile>,
     P: AsRef<Path>,
@@ -30,90 +102,300 @@ where
         exit(1)
     }
 
-    if let Some(profile) = profile {
-        let profile = profile.as_ref();
-        let base_env = profile.envs_dir().join("base.env");
-
-        if !base_env.exists() {
-            error!("Base environment '{}' does not exist.", base_env.display());
-            error!("Refusing to execute commands without a defined environment.");
-            exit(1)
-        }
-
-        fs::copy("/usr/lib/lfstage/envs/internal.env", "/tmp/lfstage/bashenv")?;
-
-        let mut f = File::options().append(true).open("/tmp/lfstage/bashenv")?;
-
-        let appended_env = format!(
-            "export ENVS={envs_dir}
-export SCRIPTS={scripts_dir}
-export JOBS={jobs}
-export LFSTAGE_PROFILE={profile}
-export LFSTAGE_VERSION={version}
-source {rcfile} || exit 2",
-            envs_dir = profile.envs_dir().display(),
-            scripts_dir = profile.scripts_dir().display(),
-            jobs = &CONFIG.jobs,
-            rcfile = base_env.display(),
-            profile = &profile.name,
-            version = env!("CARGO_PKG_VERSION")
-        );
-
-        f.write_all(appended_env.as_bytes())?;
+    let profile = profile.as_ref().map(AsRef::as_ref);
+    let mut env_file = write_bashenv(profile)?;
+    if !env.is_empty() {
+        let vars = env.iter().map(|(k, v)| (k.as_str(), OsStr::new(v))).collect::<Vec<_>>();
+        write_env(&mut env_file, &vars)?;
     }
 
-    let mut child = Command::new("bash")
-        .env_clear()
+    let mut cmd = Command::new(&CONFIG.shell);
+    cmd.env_clear()
         .arg("--noprofile")
         .arg("--norc")
         .arg(script.as_os_str())
-        .env("BASH_ENV", "/tmp/lfstage/bashenv")
+        .env("BASH_ENV", env_file.path())
+        .stdin(Stdio::null())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .spawn()?;
+        // The script leads its own process group so everything it started can be signaled with it.
+        // It's then in the background, so it can't read from the terminal.
+        .process_group(0);
+
+    // Apply the configured umask to the child so builds don't depend on the operator's umask
+    if let Some(umask) = CONFIG.build_umask() {
+        cmd.env("LFSTAGE_UMASK", format!("{umask:03o}"));
+        // SAFETY: umask is async-signal-safe and can't fail
+        unsafe {
+            cmd.pre_exec(move || {
+                libc::umask(umask);
+                Ok(())
+            });
+        }
+    }
+
+    // Opened before spawning, so failing to create them can't leave a script running unsupervised
+    let mut stdout_log = script_log(script)?;
+    let mut stderr_log = stdout_log.as_ref().map(File::try_clone).transpose()?;
 
+    let (mut child, pgid) = spawn_script(&mut cmd, script)?;
     let stdout = child.stdout.take().expect("Handle present");
     let stderr = child.stderr.take().expect("Handle present");
 
+    // Keep the reader threads in the caller's span, so concurrent scripts' output can be told apart
+    let span = tracing::Span::current();
+    let stderr_span = span.clone();
+
     let stdout_thread = thread::spawn(move || {
+        let _span = span.enter();
         let reader = io::BufReader::new(stdout);
         for line in reader.lines().map_while(Result::ok) {
             trace!("{line}");
+            if let Some(log) = &mut stdout_log {
+                writeln!(log, "{line}").ok();
+            }
         }
     });
 
     let stderr_thread = thread::spawn(move || {
+        let _span = stderr_span.enter();
         let reader = io::BufReader::new(stderr);
         for line in reader.lines().map_while(Result::ok) {
             debug!("{line}");
+            if let Some(log) = &mut stderr_log {
+                writeln!(log, "{line}").ok();
+            }
         }
     });
 
-    let status = child.wait()?;
-    if !status.success() {
-        error!("Command failed: {status}");
-        return Err(io::Error::other(format!("Command failed: {status}")));
-    }
+    let timeout = script_timeout();
+    let status = wait_with_timeout(&mut child, pgid, timeout);
+    RUNNING.lock().unwrap_or_else(PoisonError::into_inner).retain(|&p| p != pgid);
+    let status = status?;
 
+    // The pipes close once the script and everything it started have exited
     stdout_thread.join().expect("Handle already joined");
     stderr_thread.join().expect("Handle already joined");
 
+    if KEEP_ENV_FILE.load(Ordering::Relaxed) {
+        keep_bashenv(env_file, profile)?;
+    }
+
+    let path = script.to_path_buf();
+    match (status, timeout) {
+        | (Some(status), _) if status.success() => Ok(()),
+        | (Some(status), _) => {
+            error!("Command failed: {status}");
+            if let Some(log) = script_log_path(script) {
+                error!("See '{}' for its output", log.display());
+            }
+            Err(ScriptError::Failed { path, status })
+        },
+        | (None, timeout) => {
+            let timeout = timeout.unwrap_or_default();
+            error!("Command timed out after {}s", timeout.as_secs());
+            Err(ScriptError::Timeout { path, timeout })
+        },
+    }
+}
+
+/// # Spawns a script and registers its process group in [`RUNNING`]
+///
+/// If the script can't be registered, it's killed and reaped before returning.
+fn spawn_script(cmd: &mut Command, script: &Path) -> Result<(Child, libc::pid_t), ScriptError> {
+    // Spawning while holding the lock means a script is either stopped or never started
+    let mut running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+    if STOPPING.load(Ordering::Relaxed) {
+        return Err(ScriptError::Stopping(script.to_path_buf()))
+    }
+
+    // The script was checked to exist, so not finding anything means the shell is missing
+    let mut child = cmd.spawn().map_err(|source| match source.kind() {
+        | io::ErrorKind::NotFound => ScriptError::MissingShell(CONFIG.shell.clone()),
+        | _ => ScriptError::Spawn {
+            path: script.to_path_buf(),
+            source,
+        },
+    })?;
+    match libc::pid_t::try_from(child.id()) {
+        | Ok(pgid) => {
+            running.push(pgid);
+            Ok((child, pgid))
+        },
+        | Err(e) => {
+            drop(running);
+            child.kill().ok();
+            child.wait().ok();
+            Err(io::Error::other(e).into())
+        },
+    }
+}
+
+/// # Waits for a script, killing its process group if it outlives the timeout
+///
+/// The script is reaped either way. Returns `None` if it was killed.
+fn wait_with_timeout(child: &mut Child, pgid: libc::pid_t, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>> {
+    let Some(timeout) = timeout else { return child.wait().map(Some) };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status))
+        }
+
+        if Instant::now() >= deadline {
+            // SAFETY: kill can't cause undefined behavior, and the script leads this process group
+            unsafe { libc::kill(-pgid, libc::SIGKILL) };
+            child.wait()?;
+            return Ok(None)
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// # Forwards a signal to every running script, killing those that don't exit in time
+///
+/// Each script leads its own process group, so everything it started, such as make and gcc, is
+/// signaled too. Scripts get [`STOP_GRACE`] to exit before they're killed.
+pub fn stop_scripts(signal: libc::c_int) {
+    let groups = {
+        let running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+        STOPPING.store(true, Ordering::Relaxed);
+        running.clone()
+    };
+    // SAFETY: kill can't cause undefined behavior
+    let signal_all = |signal| groups.iter().filter(|&&pgid| unsafe { libc::kill(-pgid, signal) } == 0).count();
+
+    if signal_all(signal) == 0 {
+        return
+    }
+
+    let deadline = Instant::now() + STOP_GRACE;
+    while Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100));
+        // Signal 0 only checks whether any process in the group is left
+        if signal_all(0) == 0 {
+            return
+        }
+    }
+
+    warn!("Killing scripts that didn't exit within {}s", STOP_GRACE.as_secs());
+    signal_all(libc::SIGKILL);
+}
+
+/// # Returns the path of the log file for a script's output
+///
+/// The file is named after the script. Returns `None` if per-script logging isn't set up, see
+/// [`SCRIPT_LOG_DIR`].
+fn script_log_path(script: &Path) -> Option<PathBuf> {
+    let dir = SCRIPT_LOG_DIR.get()?;
+    let mut name = script.file_name().unwrap_or(script.as_os_str()).to_os_string();
+    name.push(".log");
+    Some(dir.join(name))
+}
+
+/// # Creates the log file for a script's output, truncating it
+fn script_log(script: &Path) -> io::Result<Option<File>> { script_log_path(script).map(File::create).transpose() }
+
+/// # Writes the environment a script is executed with
+///
+/// Each execution gets its own temporary file, seeded from `internal.env`. With a profile, the
+/// profile's variables are exported and its `base.env` is sourced. The file is deleted once it's
+/// dropped.
+fn write_bashenv(profile: Option<&Profile>) -> io::Result<NamedTempFile> {
+    mkdir_p("/tmp/lfstage")?;
+    let mut f = tempfile::Builder::new().prefix("bashenv.").tempfile_in("/tmp/lfstage")?;
+    f.write_all(&fs::read("/usr/lib/lfstage/envs/internal.env")?)?;
+
+    let Some(profile) = profile else { return Ok(f) };
+
+    let base_env = profile.envs_dir().join("base.env");
+    if !base_env.exists() {
+        error!("Base environment '{}' does not exist.", base_env.display());
+        error!("Refusing to execute commands without a defined environment.");
+        exit(1)
+    }
+
+    let jobs = jobs().to_string();
+    let envs_dir = profile.envs_dir();
+    let scripts_dir = profile.scripts_dir();
+    write_env(&mut f, &[
+        ("ENVS", envs_dir.as_os_str()),
+        ("SCRIPTS", scripts_dir.as_os_str()),
+        ("JOBS", OsStr::new(&jobs)),
+        ("LFSTAGE_PROFILE", OsStr::new(profile.name())),
+        ("LFSTAGE_VERSION", OsStr::new(env!("CARGO_PKG_VERSION"))),
+    ])?;
+
+    let mut source = b"source ".to_vec();
+    source.extend(shell_quote(base_env.as_os_str()));
+    source.extend(b" || exit 2\n");
+    f.write_all(&source)?;
+
+    Ok(f)
+}
+
+/// # Retains the environment a script was executed with
+///
+/// The file is moved to `bashenv` in the profile's tmp dir, or in `/tmp/lfstage` without a profile,
+/// replacing the one kept from the previous execution.
+fn keep_bashenv(env_file: NamedTempFile, profile: Option<&Profile>) -> io::Result<()> {
+    let dir = profile.map_or_else(|| PathBuf::from("/tmp/lfstage"), Profile::tmp_dir);
+    mkdir_p(&dir)?;
+
+    let kept = dir.join("bashenv");
+    env_file.persist(&kept).map_err(|e| e.error)?;
+    info!("Kept environment file '{}'", kept.display());
+    Ok(())
+}
+
+/// # Writes environment variables as shell exports
+///
+/// Values are written as raw bytes and single-quoted, so they may contain spaces, quotes, `=`, `$`,
+/// newlines, or invalid UTF-8 without being mangled or interpreted by the shell.
+fn write_env<W: Write>(w: &mut W, vars: &[(&str, &OsStr)]) -> io::Result<()> {
+    w.write_all(b"\n")?;
+    for (key, value) in vars {
+        let mut line = format!("export {key}=").into_bytes();
+        line.extend(shell_quote(value));
+        line.push(b'\n');
+        w.write_all(&line)?;
+    }
     Ok(())
 }
 
+/// # Single-quotes a value for the shell
+///
+/// Embedded single quotes are written as `'\''`.
+fn shell_quote(value: &OsStr) -> Vec<u8> {
+    let mut quoted = vec![b'\''];
+    for &b in value.as_bytes() {
+        match b {
+            | b'\'' => quoted.extend(b"'\\''"),
+            | _ => quoted.push(b),
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
 #[macro_export]
 macro_rules! exec {
-    // Pattern: profile and a script
-    ($profile:expr; $script:expr) => {{
+    // Pattern: profile, a script, and extra environment variables
+    ($profile:expr; $script:expr; $env:expr) => {{
         use std::path::Path;
         debug!(
             "Using profile '{}' to execute script '{}'",
             $crate::profile::Profile::new($profile),
             Path::new($script).display(),
         );
-        $crate::utils::cmd::exec(Some($profile), $script)
+        $crate::utils::cmd::exec(Some($profile), $script, $env)
     }};
 
+    // Pattern: profile and a script
+    ($profile:expr; $script:expr) => {{ $crate::exec!($profile; $script; &[]) }};
+
     // Pattern: just a script
     ($script:expr) => {{
         use std::path::Path;
@@ -121,14 +403,73 @@ macro_rules! exec {
         use $crate::profile::Profile;
 
         debug!("Executing {} without a profile", Path::new($script).display(),);
-        $crate::utils::cmd::exec::<&Profile, _>(None, $script)
+        $crate::utils::cmd::exec::<&Profile, _>(None, $script, &[])
     }};
 }
 
 #[cfg(test)]
 mod test {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+    use std::time::{Duration, Instant};
+
+    use super::{shell_quote, wait_with_timeout, write_env};
     use crate::profile::Profile;
 
+    /// Values that would be mangled or interpreted if interpolated without quoting
+    const TRICKY: &[&[u8]] = &[
+        b"plain",
+        b"with spaces",
+        b"key=value=more",
+        b"it's \"quoted\"",
+        b"'''",
+        b"$HOME `id` $(id) \\n",
+        b"multi\nline",
+        b"",
+        b"\xff\xfenot utf-8",
+    ];
+
+    #[test]
+    fn shell_quote_escapes_single_quotes() {
+        assert_eq!(shell_quote(OsStr::new("a'b")), b"'a'\\''b'");
+        assert_eq!(shell_quote(OsStr::new("")), b"''");
+    }
+
+    #[test]
+    fn write_env_round_trips_tricky_values() {
+        for value in TRICKY {
+            let mut env = Vec::new();
+            write_env(&mut env, &[("TRICKY", OsStr::from_bytes(value))]).expect("Writing to a Vec can't fail");
+            env.extend(b"printf '%s' \"$TRICKY\"");
+
+            let output = Command::new("bash")
+                .env_clear()
+                .arg("-c")
+                .arg(OsStr::from_bytes(&env))
+                .output()
+                .expect("Failed to run bash");
+            assert_eq!(output.stdout, *value);
+        }
+    }
+
+    #[test]
+    fn wait_with_timeout_kills_process_group() {
+        let mut child = Command::new("bash")
+            .arg("-c")
+            .arg("sleep 30 & sleep 30")
+            .process_group(0)
+            .spawn()
+            .expect("Failed to run bash");
+
+        let start = Instant::now();
+        let pgid = libc::pid_t::try_from(child.id()).expect("pid should fit");
+        let status = wait_with_timeout(&mut child, pgid, Some(Duration::from_millis(200))).expect("Failed to wait");
+        assert!(status.is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
     #[test]
     fn exec_no_profile() { assert!(exec!("s"; "/usr/lib/lfstage/scripts/testing.sh").is_ok()) }
 
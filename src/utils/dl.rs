@@ -1,26 +1,47 @@
 // utils/dl.rs
 //! Utilities related to downloading
 
-use std::fs::{self, File};
-use std::io::{self, Write};
-use std::path::{Path, PathBuf};
-use std::process::{Command, exit};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, LazyLock};
-use std::time::Duration;
-use std::{fmt, string};
+use std::sync::{Arc, LazyLock, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, iter};
 
 use futures::StreamExt;
 use futures::future::join_all;
-use permitit::Permit;
-use reqwest::Client;
-use reqwest::header::{HeaderMap, USER_AGENT};
+use glob::Pattern;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressFinish, ProgressStyle};
+use reqwest::header::{CONTENT_LENGTH, CONTENT_RANGE, ETAG, HeaderMap, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE, USER_AGENT};
 use reqwest::redirect::Policy;
+use reqwest::{Client, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
+use tokio::sync::Semaphore;
 use tokio::task;
+use tokio_util::sync::CancellationToken;
 
+use crate::config::{CONFIG, HttpVersion};
 use crate::profile::Profile;
+use crate::utils::cas;
+use crate::utils::cmd::jobs;
+use crate::utils::init::exit;
+use crate::utils::size::parse_size;
+
+/// The maximum number of redirects followed
+const MAX_REDIRECTS: usize = 32;
+
+/// The client used for all requests, except to `insecure_tls_hosts`
+static CLIENT: LazyLock<Client> = LazyLock::new(|| build_client(false));
+
+/// The client used for requests to `insecure_tls_hosts`, which accepts invalid certificates
+static INSECURE_CLIENT: LazyLock<Client> = LazyLock::new(|| build_client(true));
 
 // TODO: Documentation
 // NOTE: Beware the distinction between timeout and connect_timeout
@@ -28,26 +49,152 @@ use crate::profile::Profile;
 /// # Creates a reqwest client
 ///
 /// This client follows up to 32 redirects and has a connection timeout of 120 seconds. It also
-/// sets the user agent to crate/version.
+/// sets the user agent (see [`user_agent`]), and negotiates the HTTP version set in the config.
+///
+/// If `insecure` is set, the client accepts invalid certificates, and refuses to follow redirects
+/// to hosts that aren't in `insecure_tls_hosts`.
 #[allow(clippy::expect_used)]
-static CLIENT: LazyLock<Client> = LazyLock::new(|| {
-    let user_agent = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-    Client::builder()
-        .redirect(Policy::limited(32))
-        .default_headers({
-            let mut headers = HeaderMap::new();
-            headers.insert(USER_AGENT, user_agent.parse().expect("User agent is invalid"));
-            headers
-        })
-        .connect_timeout(Duration::from_mins(2))
-        .build()
-        .expect("Failed to build client")
+fn build_client(insecure: bool) -> Client {
+    let user_agent = user_agent();
+    let builder = match CONFIG.http_version {
+        | HttpVersion::Auto => Client::builder(),
+        | HttpVersion::Http1 => Client::builder().http1_only(),
+        | HttpVersion::Http2 => Client::builder().http2_prior_knowledge(),
+    };
+
+    let policy = match insecure {
+        | false => Policy::limited(MAX_REDIRECTS),
+        | true => Policy::custom(|attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if attempt.url().host_str().is_some_and(is_insecure_host) {
+                attempt.follow()
+            } else {
+                attempt.error("refusing to follow a redirect away from an insecure TLS host")
+            }
+        }),
+    };
+
+    let mut builder = builder.redirect(policy).tls_danger_accept_invalid_certs(insecure).default_headers({
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, user_agent.parse().expect("User agent is invalid"));
+        headers
+    });
+
+    // Zero disables either timeout
+    if CONFIG.connect_timeout > 0 {
+        builder = builder.connect_timeout(Duration::from_secs(CONFIG.connect_timeout));
+    }
+    if CONFIG.request_timeout > 0 {
+        builder = builder.timeout(Duration::from_secs(CONFIG.request_timeout));
+    }
+
+    builder.build().expect("Failed to build client")
+}
+
+/// # Whether certificates for a host aren't verified, per `insecure_tls_hosts`
+fn is_insecure_host(host: &str) -> bool { CONFIG.insecure_tls_hosts.iter().any(|h| h.eq_ignore_ascii_case(host)) }
+
+/// # Picks the client for a URL
+///
+/// Only requests to `insecure_tls_hosts` use a client that accepts invalid certificates, which is
+/// warned about every time.
+fn client_for(url: &str) -> &'static Client {
+    let host = Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_owned));
+    match host {
+        | Some(host) if is_insecure_host(&host) => {
+            warn!("Using an insecure TLS connection to '{host}' for '{url}'");
+            &INSECURE_CLIENT
+        },
+        | _ => &CLIENT,
+    }
+}
+
+/// # Builds the user agent
+///
+/// The user agent is crate/version, followed by `user_agent_comment` from the config in
+/// parentheses if it's set, e.g. `lfstage/3.0.0 (admin@example.com)`. Mirror operators may use the
+/// comment to get in touch. An invalid comment is ignored.
+fn user_agent() -> String {
+    let base = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let Some(comment) = CONFIG.user_agent_comment.as_deref() else { return base };
+
+    let valid = !comment.trim().is_empty() && comment.chars().all(|c| (c.is_ascii_graphic() || c == ' ') && c != '(' && c != ')');
+
+    if !valid {
+        warn!("Invalid user_agent_comment '{comment}', using the plain user agent");
+        return base
+    }
+
+    format!("{base} ({})", comment.trim())
+}
+
+/// Whether every source must have a checksum for this invocation, see `--require-checksum`
+pub static REQUIRE_CHECKSUM: AtomicBool = AtomicBool::new(false);
+
+/// The delay before retrying a mirror that already failed
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// The bandwidth limit shared by every download, per `max_download_rate`
+static RATE_LIMIT: LazyLock<Option<RateLimit>> = LazyLock::new(|| {
+    let rate = CONFIG.max_download_rate.as_deref()?;
+    match parse_size(rate.trim().trim_end_matches("/s")) {
+        | Ok(bytes_per_sec) => Some(RateLimit::new(bytes_per_sec)),
+        | Err(e) => {
+            warn!("Invalid max_download_rate: {e}. Not limiting downloads");
+            None
+        },
+    }
 });
 
+/// # A bandwidth limit
+///
+/// Every transfer reserves the next slot of time its bytes take at the limit, so the limit holds
+/// across any number of concurrent downloads.
+struct RateLimit {
+    bytes_per_sec: u64,
+
+    /// When the bytes reserved so far will have been transferred at the limit
+    next: Mutex<Instant>,
+}
+
+impl RateLimit {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// # Waits until `n` more bytes may have been transferred
+    async fn throttle(&self, n: u64) {
+        let nanos = u128::from(n) * 1_000_000_000 / u128::from(self.bytes_per_sec);
+        let slot = Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX));
+
+        let until = {
+            let mut next = self.next.lock().unwrap_or_else(PoisonError::into_inner);
+            *next = (*next).max(Instant::now()) + slot;
+            *next
+        };
+        tokio::time::sleep_until(until.into()).await;
+    }
+}
+
 #[derive(Debug)]
 pub struct Download {
-    pub url:  String,
-    pub dest: String,
+    pub url:     String,
+    pub mirrors: Vec<String>,
+    pub dest:    String,
+    pub sha256:  Option<String>,
+    /// The ref to check out, if this is a git repository
+    pub git_ref: Option<String>,
+    /// The URL of a detached signature to verify the source with
+    pub sig:     Option<String>,
+}
+
+impl Download {
+    /// # Iterates over the primary URL, followed by any mirrors
+    pub fn urls(&self) -> impl Iterator<Item = &str> { iter::once(self.url.as_str()).chain(self.mirrors.iter().map(String::as_str)) }
 }
 
 impl fmt::Display for Download {
@@ -58,106 +205,1111 @@ impl FromStr for Download {
     type Err = DownloadError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if let Some((u, f)) = s.split_once(" -> ") {
-            return Ok(Self {
-                url:  u.to_string(),
-                dest: f.to_string(),
-            });
+        let (urls, dest) = match s.split_once(" -> ") {
+            | Some((u, f)) => (u, Some(f)),
+            | None => (s, None),
+        };
+        let (urls, sig) = match urls.split_once(" ^sig=") {
+            | Some((u, sig)) => (u, Some(sig.trim()).filter(|sig| !sig.is_empty())),
+            | None => (urls, None),
+        };
+
+        // Split off the checksum, if any. It may be given on any mirror, but must agree across them.
+        // The same goes for the ref of a git repository.
+        let mut sha256 = None;
+        let mut git_ref = None;
+        let mut repos = 0;
+        let mut parsed = Vec::new();
+        for url in urls.split(" | ").map(str::trim) {
+            let url = match url.split_once("#sha256=") {
+                | Some((u, h)) => {
+                    let h = parse_sha256(h)?;
+                    if sha256.as_ref().is_some_and(|s| *s != h) {
+                        return Err(DownloadError::InvalidChecksum(h));
+                    }
+                    sha256 = Some(h);
+                    u
+                },
+                | None => url,
+            };
+            let url = match parse_git(url)? {
+                | Some((repo, r)) => {
+                    if git_ref.is_some_and(|g| g != r) {
+                        return Err(DownloadError::InvalidUrl(format!("{s}: mirrors name different refs")));
+                    }
+                    git_ref = Some(r);
+                    repos += 1;
+                    repo
+                },
+                | None => url,
+            };
+            parsed.push(url.to_string());
+        }
+
+        if repos > 0 && repos < parsed.len() {
+            return Err(DownloadError::InvalidUrl(format!("{s}: repositories can't be mixed with files")));
+        }
+        if git_ref.is_some() && (sha256.is_some() || sig.is_some()) {
+            return Err(DownloadError::InvalidUrl(format!("{s}: repositories can't have checksums or signatures")));
         }
 
-        let (_, f) = s.rsplit_once('/').ok_or_else(|| DownloadError::InvalidUrl(s.to_string()))?;
+        let (url, mirrors) = parsed.split_first().ok_or_else(|| DownloadError::InvalidUrl(s.to_string()))?;
+        let dest = match dest {
+            | Some(f) => parse_dest(f.trim())?,
+            | None => {
+                let name = url.rsplit_once('/').ok_or_else(|| DownloadError::InvalidUrl(s.to_string()))?.1;
+                match git_ref {
+                    | Some(_) => name.trim_end_matches(".git"),
+                    | None => name,
+                }
+            },
+        };
+
         Ok(Self {
-            url:  s.to_string(),
-            dest: f.to_string(),
+            url: url.clone(),
+            mirrors: mirrors.to_vec(),
+            dest: dest.to_string(),
+            sha256,
+            git_ref: git_ref.map(str::to_string),
+            sig: sig.map(str::to_string),
         })
     }
 }
 
+/// # Validates a destination given with ` -> `
+///
+/// Destinations may be nested in subdirectories of the sources directory, e.g. `patches/foo.patch`,
+/// but may not be absolute or contain `..`, so they can't point outside of it.
+fn parse_dest(dest: &str) -> Result<&str, DownloadError> {
+    let valid = !dest.is_empty() && Path::new(dest).components().all(|c| matches!(c, Component::Normal(_)));
+    match valid {
+        | true => Ok(dest),
+        | false => Err(DownloadError::InvalidDest(dest.to_string())),
+    }
+}
+
+/// # Splits a git repository URL into the repository and the ref to check out
+///
+/// Repositories are written as `git+<url>#tag=<tag>`, with `#commit=` or `#branch=` in place of
+/// `#tag=` for other refs. Refs starting with `-` are rejected, since git would take them for an
+/// option. Returns `None` if the URL isn't a repository.
+fn parse_git(url: &str) -> Result<Option<(&str, &str)>, DownloadError> {
+    let Some(url) = url.strip_prefix("git+") else { return Ok(None) };
+
+    let git_ref = url
+        .split_once('#')
+        .and_then(|(repo, spec)| {
+            ["tag=", "commit=", "branch="]
+                .iter()
+                .find_map(|key| spec.strip_prefix(key))
+                .filter(|r| !r.is_empty())
+                .map(|r| (repo, r))
+        })
+        .ok_or_else(|| DownloadError::InvalidUrl(format!("{url}: expected a ref, e.g. #tag=<tag>")))?;
+    if git_ref.1.starts_with('-') {
+        return Err(DownloadError::InvalidUrl(format!("{url}: refs may not start with '-'")));
+    }
+    Ok(Some(git_ref))
+}
+
+/// # Hashes a file, returning the lowercase hex digest
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// # Validates and normalizes a hex-encoded SHA-256 digest
+fn parse_sha256(s: &str) -> Result<String, DownloadError> {
+    let s = s.trim();
+    if s.len() != 64 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(DownloadError::InvalidChecksum(s.to_string()));
+    }
+    Ok(s.to_ascii_lowercase())
+}
+
+/// # Options controlling how sources are downloaded
+#[derive(Debug, Default, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct DownloadOptions {
+    /// Whether to download sources that already exist
+    pub force: bool,
+
+    /// Whether to sum the sizes of all sources up front and display an aggregate progress bar
+    pub show_total: bool,
+
+    /// Whether to only download the sources that failed during the previous run
+    pub resume: bool,
+
+    /// If non-empty, only download sources whose destination matches one of these
+    pub include: Vec<Pattern>,
+
+    /// Don't download sources whose destination matches one of these
+    pub exclude: Vec<Pattern>,
+
+    /// Where to write the download report as JSON, if anywhere
+    pub report: Option<PathBuf>,
+
+    /// Only download extant sources again if they changed upstream since this time
+    pub since: Option<SystemTime>,
+
+    /// Whether to revalidate extant sources with their stored `ETag`s
+    pub etags: bool,
+
+    /// Whether to cancel the remaining downloads once one fails
+    pub fail_fast: bool,
+
+    /// Whether to only download sources matching the checksums in the lockfile
+    pub frozen: bool,
+
+    /// How many times to retry a failed download, defaults to `download_retries` from the config
+    pub retries: Option<usize>,
+}
+
+impl DownloadOptions {
+    /// # Filters downloads by their destination
+    ///
+    /// Includes are applied first, then excludes.
+    pub fn filter(&self, dls: &mut Vec<Download>) {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return
+        }
+
+        let total = dls.len();
+        dls.retain(|dl| self.include.is_empty() || self.include.iter().any(|p| p.matches(&dl.dest)));
+        dls.retain(|dl| !self.exclude.iter().any(|p| p.matches(&dl.dest)));
+        info!("{} of {total} sources matched the filters", dls.len());
+    }
+}
+
+/// # A source as recorded in the lockfile
+#[derive(Debug, Serialize, Deserialize)]
+struct LockedSource {
+    url:    String,
+    bytes:  u64,
+    sha256: String,
+}
+
+/// # What happened to a source during a download
+#[derive(Debug, Serialize)]
+#[serde(tag = "outcome", rename_all = "lowercase")]
+pub enum Outcome {
+    /// The source was downloaded
+    Fetched {
+        bytes: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        etag:  Option<String>,
+    },
+
+    /// The source already existed
+    Skipped,
+
+    /// The source could not be downloaded
+    Failed { error: String },
+
+    /// The download was cancelled because another source failed
+    Cancelled,
+}
+
+/// # The outcome of downloading a single source
+#[derive(Debug, Serialize)]
+pub struct SourceReport {
+    pub url:     String,
+    pub dest:    String,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    pub secs:    f64,
+}
+
+/// # A structured record of a download run
+#[derive(Debug, Default, Serialize)]
+pub struct DownloadReport {
+    pub sources: Vec<SourceReport>,
+}
+
+impl DownloadReport {
+    /// # Iterates over the sources that failed
+    pub fn failed(&self) -> impl Iterator<Item = &SourceReport> { self.sources.iter().filter(|s| matches!(s.outcome, Outcome::Failed { .. })) }
+
+    /// # Describes the first source that failed, if any
+    pub fn first_failure(&self) -> Option<DownloadError> {
+        self.sources.iter().find_map(|s| match &s.outcome {
+            | Outcome::Failed { error } => Some(DownloadError::Source {
+                dest:   s.dest.clone(),
+                reason: error.clone(),
+            }),
+            | _ => None,
+        })
+    }
+
+    /// # Writes the report as pretty-printed JSON
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, json + "\n")
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum DownloadError {
     #[error("Invalid URL: {0}")]
     InvalidUrl(String),
 
+    #[error("Invalid destination '{0}', expected a relative path without '..'")]
+    InvalidDest(String),
+
     #[error("Extant file: {0}")]
     Extant(PathBuf),
 
+    #[error("Invalid checksum: {0}")]
+    InvalidChecksum(String),
+
+    #[error("Checksum mismatch for {path}: expected {expected}, got {actual}")]
+    Checksum {
+        path:     PathBuf,
+        expected: String,
+        actual:   String,
+    },
+
+    #[error("Size mismatch for {path}: expected {expected} bytes, got {actual}")]
+    Length {
+        path:     PathBuf,
+        expected: u64,
+        actual:   u64,
+    },
+
+    #[error("Signature verification failed for {path}: {reason}")]
+    Signature { path: PathBuf, reason: String },
+
+    #[error("No keyring to verify the signature of {0} against, set `keyring` or `verify_signatures = false`")]
+    NoKeyring(PathBuf),
+
     #[error("I/O Error: {0}")]
     Io(#[from] io::Error),
 
-    #[error("UTF-8 Error: {0}")]
-    FromUtf8(#[from] string::FromUtf8Error),
+    #[error("Failed to download {0} source(s)")]
+    Failed(usize),
+
+    #[error("Failed to download {dest}: {reason}")]
+    Source { dest: String, reason: String },
+
+    #[error("Invalid sources list at line {line}: {reason}")]
+    SourcesLine { line: usize, reason: String },
+
+    #[error("Invalid sources list at lines {}", join_lines(.0))]
+    SourcesLines(Vec<usize>),
+
+    #[error("Sources without a checksum at line(s) {}", join_lines(.0))]
+    MissingChecksums(Vec<usize>),
+
+    #[error("Sources missing from the lockfile: {}", .0.join(", "))]
+    Unlocked(Vec<String>),
+
+    #[error("Source already listed: {0}")]
+    DuplicateSource(String),
+
+    #[error("Source not listed: {0}")]
+    UnlistedSource(String),
 
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
 }
 
-async fn download_file<P: AsRef<Path>>(url: &str, file_path: P, download_extant: bool) -> Result<(), DownloadError> {
+/// # Lists the sources to retry with `--resume-download`
+///
+/// These are the sources that failed or were cancelled in this run, followed by those recorded as
+/// failed by an earlier run that weren't attempted in this one, e.g. because `--include` or
+/// `--exclude` filtered them out. Forgetting those would make `--resume-download` skip them.
+fn failed_sources(previous: &str, report: &DownloadReport) -> Vec<String> {
+    let attempted = |dest: &str| report.sources.iter().any(|s| s.dest == dest);
+    let failed = report
+        .sources
+        .iter()
+        .filter(|s| matches!(s.outcome, Outcome::Failed { .. } | Outcome::Cancelled))
+        .map(|s| s.dest.clone());
+    let untried = previous.lines().map(str::trim).filter(|d| !d.is_empty() && !attempted(d)).map(str::to_string);
+
+    failed.chain(untried).collect()
+}
+
+/// # Joins line numbers for display
+fn join_lines(lines: &[usize]) -> String { lines.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ") }
+
+/// # Downloads a file, rotating through its mirrors on failure
+///
+/// Each retry advances to the next mirror, wrapping around to the primary URL, so a mirror that
+/// failed isn't tried again until the others have had a turn. Every mirror is tried at least once,
+/// and at least `retries` retries are made before giving up. Extant files aren't retried.
+async fn download_with_mirrors(
+    dl: &Download,
+    file_path: &Path,
+    retries: usize,
+    download_extant: bool,
+    revalidate: Revalidate<'_>,
+    progress: &Progress,
+) -> Result<Fetched, DownloadError> {
+    // Copy missing sources out of the store instead of downloading them. Extant ones are left to
+    // the usual checks, since copying them again on every run would be wasteful.
+    if !download_extant
+        && !file_path.exists()
+        && let Some(entry) = dl.sha256.as_deref().and_then(cas::get)
+    {
+        cas::reflink(&entry, file_path)?;
+        debug!("Copied '{}' from the source store", file_path.display());
+        return Err(DownloadError::Extant(file_path.to_owned()));
+    }
+
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let urls = dl.urls().collect::<Vec<_>>();
+    let attempts = (retries + 1).max(urls.len());
+
+    let mut attempt = 0;
+    loop {
+        let url = urls[attempt % urls.len()];
+        attempt += 1;
+        debug!("Attempt {attempt}/{attempts} for '{}' using '{url}'", dl.dest);
+
+        let result = match (&dl.git_ref, local_path(url)) {
+            | (Some(git_ref), _) => fetch_git(url, git_ref, file_path).await,
+            | (None, Some(path)) => copy_local(dl, path, file_path, download_extant).await,
+            | (None, None) => download_file(dl, url, file_path, download_extant, revalidate, progress).await,
+        };
+
+        let e = match result {
+            | Err(DownloadError::Extant(path)) => return Err(DownloadError::Extant(path)),
+            | Err(e) => e,
+            | Ok(fetched) => {
+                if url != dl.url {
+                    info!("Fell back to mirror '{url}' for '{}'", dl.dest);
+                }
+                return Ok(fetched)
+            },
+        };
+
+        if attempt >= attempts {
+            return Err(e);
+        }
+        warn!("Attempt {attempt}/{attempts} for '{}' using '{url}' failed: {e}", dl.dest);
+
+        // Only back off once every mirror has been tried
+        if attempt >= urls.len() {
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    }
+}
+
+/// # Downloads a file from a URL
+///
+/// If the download has a checksum, the file is hashed incrementally as chunks arrive, so
+/// verification costs next to nothing once the download completes. On a mismatch, the part file
+/// is removed.
+///
+/// A part file left behind by an interrupted download is resumed with a range request, see
+/// [`resumable_part`]. If the server doesn't honor the range, the download starts over.
+///
+/// Extant files are skipped unless `download_extant` is set, or they can be revalidated. In that
+/// case they're requested conditionally, preferring the `ETag` over the modification time, and only
+/// downloaded again if they changed upstream.
+async fn download_file<P: AsRef<Path>>(
+    dl: &Download,
+    url: &str,
+    file_path: P,
+    download_extant: bool,
+    revalidate: Revalidate<'_>,
+    progress: &Progress,
+) -> Result<Fetched, DownloadError> {
     let file_path = file_path.as_ref();
+    let extant = file_path.exists() && !download_extant;
 
     // Skip extant files
-    if file_path.exists() && !download_extant {
+    if extant && revalidate.is_none() {
         debug!("Skipping download for extant file '{}'", file_path.display());
         return Err(DownloadError::Extant(file_path.to_owned()));
     }
 
-    // Fetch the url
-    let resp = CLIENT.get(url).send().await?.error_for_status()?;
+    // Fetch the url, conditionally for extant files
+    let mut req = client_for(url).get(url);
+    if extant {
+        match revalidate {
+            | Revalidate { etag: Some(etag), .. } => req = req.header(IF_NONE_MATCH, etag),
+            | Revalidate { since: Some(since), .. } => req = req.header(IF_MODIFIED_SINCE, httpdate::fmt_http_date(since)),
+            | _ => {},
+        }
+    }
 
-    info!("Downloading '{url}'");
-    // Create a part file
+    // Resume a part file left behind by an earlier attempt
     let partfile_str = format!("{}.part", file_path.display());
-    let mut partfile = File::create(&partfile_str)?;
+    let (resume_from, part_etag) = resumable_part(&partfile_str, url, download_extant);
+    if resume_from > 0 {
+        req = req.header(RANGE, format!("bytes={resume_from}-"));
+        if let Some(etag) = part_etag {
+            req = req.header(IF_RANGE, etag);
+        }
+    }
+
+    let resp = req.send().await?;
+    // Some servers ignore If-Modified-Since, so the modification time is compared here too. A
+    // changed ETag is trusted over it.
+    let last_modified = resp.headers().get(LAST_MODIFIED).and_then(|v| httpdate::parse_http_date(v.to_str().ok()?).ok());
+    let unmodified = extant && revalidate.etag.is_none() && revalidate.since.is_some_and(|since| !modified_since(last_modified, since));
+    if resp.status() == StatusCode::NOT_MODIFIED || (resp.status().is_success() && unmodified) {
+        debug!("Skipping download for unmodified file '{}'", file_path.display());
+        return Err(DownloadError::Extant(file_path.to_owned()));
+    }
+    if resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The part file can't be resumed, so the next attempt starts over
+        fs::remove_file(&partfile_str)?;
+    }
+    let resp = resp.error_for_status()?;
+    let etag = resp.headers().get(ETAG).and_then(|v| v.to_str().ok()).map(str::to_owned);
+
+    let mut hasher = dl.sha256.as_ref().map(|_| Sha256::new());
+    let mut partfile = open_partfile(&resp, url, &partfile_str, resume_from, hasher.as_mut())?;
+    let done = partfile.metadata()?.len();
+    let expected = resp.content_length();
+    let pb = progress.start(&dl.dest, expected.map(|len| len + done), done);
+
     let mut stream = resp.bytes_stream();
+    let mut bytes = 0;
 
     // Write the file
     while let Some(chunk) = stream.next().await {
         let data = chunk.inspect_err(|e| error!("Invalid chunk: {e}"))?;
         partfile.write_all(&data)?;
+        bytes += data.len() as u64;
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&data);
+        }
+        progress.inc(&dl.dest, &pb, data.len() as u64);
+        if let Some(limit) = &*RATE_LIMIT {
+            limit.throttle(data.len() as u64).await;
+        }
     }
 
     partfile.flush()?; // paranoia
 
-    // Move the part file to its final destination
-    fs::rename(partfile_str, file_path)?;
+    // Catch downloads cut short, e.g. by a proxy, before they're mistaken for complete ones
+    if let Some(expected) = expected
+        && bytes != expected
+    {
+        fs::remove_file(&partfile_str)?;
+        return Err(DownloadError::Length {
+            path: file_path.to_owned(),
+            expected,
+            actual: bytes,
+        });
+    }
+
+    // Verify the checksum
+    if let (Some(expected), Some(hasher)) = (&dl.sha256, hasher) {
+        let actual = format!("{:x}", hasher.finalize());
+        if *expected != actual {
+            fs::remove_file(&partfile_str)?;
+            return Err(DownloadError::Checksum {
+                path: file_path.to_owned(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+        debug!("Verified checksum for '{}'", file_path.display());
+    }
+
+    // Verify the signature
+    if let Some(sig) = &dl.sig
+        && let Err(e) = verify_signature(Path::new(&partfile_str), file_path, sig).await
+    {
+        fs::remove_file(&partfile_str)?;
+        return Err(e);
+    }
+
+    // Keep the upstream modification time, so the file's age reflects the source rather than when
+    // it was downloaded
+    if let Some(last_modified) = last_modified
+        && let Err(e) = partfile.set_modified(last_modified)
+    {
+        warn!("Failed to set the modification time of '{}': {e}", file_path.display());
+    }
+
+    // Move the part file to its final destination, through the store if it's enabled
+    match &dl.sha256 {
+        | Some(sha256) if CONFIG.source_store => cas::store(Path::new(&partfile_str), sha256, file_path)?,
+        | _ => fs::rename(&partfile_str, file_path)?,
+    }
+    fs::remove_file(part_url_file(&partfile_str)).ok();
     info!("Downloaded '{}'", file_path.display());
 
+    Ok(Fetched { bytes, etag })
+}
+
+/// # Finds the path of a local source
+///
+/// Local sources are given as `file://` URLs or absolute paths. Returns `None` for anything else.
+fn local_path(url: &str) -> Option<&Path> {
+    match url.strip_prefix("file://") {
+        | Some(path) => Some(Path::new(path)),
+        | None => url.starts_with('/').then(|| Path::new(url)),
+    }
+}
+
+/// # Copies a source from the local filesystem
+///
+/// The source is hardlinked if possible, and copied otherwise. Like downloads, it's verified if it
+/// has a checksum or signature, and extant files are skipped unless `download_extant` is set. An
+/// extant file older than the original is copied again, though.
+async fn copy_local(dl: &Download, path: &Path, file_path: &Path, download_extant: bool) -> Result<Fetched, DownloadError> {
+    let meta = fs::metadata(path)?;
+    if !download_extant
+        && let Ok(extant) = fs::metadata(file_path)
+        && extant.modified()? >= meta.modified()?
+    {
+        debug!("Skipping copy for up-to-date file '{}'", file_path.display());
+        return Err(DownloadError::Extant(file_path.to_owned()));
+    }
+
+    info!("Copying '{}'", path.display());
+    let partfile = PathBuf::from(format!("{}.part", file_path.display()));
+    if partfile.exists() {
+        fs::remove_file(&partfile)?;
+    }
+    if fs::hard_link(path, &partfile).is_err() {
+        fs::copy(path, &partfile)?;
+    }
+
+    if let Some(expected) = &dl.sha256 {
+        let actual = sha256_file(&partfile)?;
+        if *expected != actual {
+            fs::remove_file(&partfile)?;
+            return Err(DownloadError::Checksum {
+                path: file_path.to_owned(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+
+    if let Some(sig) = &dl.sig
+        && let Err(e) = verify_signature(&partfile, file_path, sig).await
+    {
+        fs::remove_file(&partfile)?;
+        return Err(e);
+    }
+
+    fs::rename(&partfile, file_path)?;
+    info!("Copied '{}'", file_path.display());
+    Ok(Fetched { bytes: meta.len(), etag: None })
+}
+
+/// # Verifies a file against a detached signature with gpgv
+///
+/// The signature is fetched next to the file and removed afterwards. If no keyring is configured,
+/// the signature can't be verified, which is an error unless `verify_signatures` is turned off.
+/// Errors name `dest`, where the file is headed.
+async fn verify_signature(file: &Path, dest: &Path, sig_url: &str) -> Result<(), DownloadError> {
+    if !CONFIG.verify_signatures {
+        warn!("Not verifying the signature of '{}' since verify_signatures is off", dest.display());
+        return Ok(())
+    }
+
+    let Some(keyring) = &CONFIG.keyring else {
+        return Err(DownloadError::NoKeyring(dest.to_owned()))
+    };
+
+    let sig = client_for(sig_url).get(sig_url).send().await?.error_for_status()?.bytes().await?;
+    let mut sig_file = tempfile::NamedTempFile::new_in(file.parent().unwrap_or_else(|| Path::new(".")))?;
+    sig_file.write_all(&sig)?;
+
+    let output = tokio::process::Command::new("gpgv")
+        .arg("--keyring")
+        .arg(keyring)
+        .arg(sig_file.path())
+        .arg(file)
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(DownloadError::Signature {
+            path:   dest.to_owned(),
+            reason: stderr.lines().last().unwrap_or("gpgv failed").to_string(),
+        });
+    }
+
+    debug!("Verified signature of '{}'", dest.display());
+    Ok(())
+}
+
+/// # Fetches a ref of a git repository into a checkout
+///
+/// The repository is only cloned once. Later runs fetch the ref into the existing checkout and
+/// reset it, discarding any changes, so nothing is cloned again. Only the ref's commit is fetched,
+/// without history.
+async fn fetch_git(url: &str, git_ref: &str, dir: &Path) -> Result<Fetched, DownloadError> {
+    if dir.join(".git").is_dir() {
+        info!("Updating '{url}' to '{git_ref}'");
+        git(dir, &["remote", "set-url", "origin", url]).await?;
+    } else {
+        info!("Cloning '{url}' at '{git_ref}'");
+        fs::create_dir_all(dir)?;
+        git(dir, &["init", "-q"]).await?;
+        git(dir, &["remote", "add", "origin", url]).await?;
+    }
+
+    git(dir, &["fetch", "-q", "--depth", "1", "origin", git_ref]).await?;
+    git(dir, &["reset", "-q", "--hard", "FETCH_HEAD"]).await?;
+    git(dir, &["clean", "-q", "-dffx"]).await?;
+    info!("Checked out '{git_ref}' in '{}'", dir.display());
+
+    Ok(Fetched { bytes: 0, etag: None })
+}
+
+/// # Runs git in a directory
+async fn git(dir: &Path, args: &[&str]) -> io::Result<()> {
+    let status = tokio::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .status()
+        .await?;
+
+    if !status.success() {
+        return Err(io::Error::other(format!("git {} failed: {status}", args.join(" "))));
+    }
+    Ok(())
+}
+
+/// # Whether an upstream file changed since a time, going by its `Last-Modified` header
+///
+/// Without the header, the file is assumed to have changed.
+fn modified_since(last_modified: Option<SystemTime>, since: SystemTime) -> bool { last_modified.is_none_or(|lm| lm > since) }
+
+/// # Returns the file recording where a part file was downloaded from
+fn part_url_file(partfile: &str) -> String { format!("{partfile}.url") }
+
+/// # Finds how much of a part file can be resumed
+///
+/// A part file is only resumed if it was downloaded from the same URL, as recorded next to it by
+/// [`open_partfile`], and the download isn't forced. Otherwise, a different mirror or a changed
+/// source could be spliced onto it. Returns the length to resume from, and the `ETag` the part was
+/// downloaded with, if any, so the server can send the whole file if it changed since.
+fn resumable_part(partfile: &str, url: &str, download_extant: bool) -> (u64, Option<String>) {
+    let Ok(meta) = fs::metadata(partfile) else { return (0, None) };
+    let recorded = fs::read_to_string(part_url_file(partfile)).unwrap_or_default();
+    let mut lines = recorded.lines();
+    if download_extant || lines.next() != Some(url) {
+        return (0, None)
+    }
+
+    (meta.len(), lines.next().filter(|etag| !etag.is_empty()).map(str::to_owned))
+}
+
+/// # Opens the part file a response is written to
+///
+/// A partial response from `resume_from` appends to the part file, whose contents are fed to the
+/// hasher first. A full response truncates it, and the URL and `ETag` it came from are recorded for
+/// [`resumable_part`]. A partial response from anywhere else can't be used, so the part file is
+/// removed and the next attempt starts over.
+fn open_partfile(resp: &reqwest::Response, url: &str, partfile: &str, resume_from: u64, hasher: Option<&mut Sha256>) -> io::Result<File> {
+    if resp.status() != StatusCode::PARTIAL_CONTENT {
+        info!("Downloading '{url}'");
+        let etag = resp.headers().get(ETAG).and_then(|v| v.to_str().ok()).unwrap_or_default();
+        fs::write(part_url_file(partfile), format!("{url}\n{etag}\n"))?;
+        return File::create(partfile)
+    }
+
+    let content_range = resp.headers().get(CONTENT_RANGE).and_then(|v| v.to_str().ok());
+    if !content_range.is_some_and(|v| v.starts_with(&format!("bytes {resume_from}-"))) {
+        fs::remove_file(partfile)?;
+        return Err(io::Error::other(format!("Unexpected range {content_range:?} resuming '{url}'")));
+    }
+
+    info!("Resuming '{url}' from {}", HumanBytes(resume_from));
+    let mut file = OpenOptions::new().read(true).append(true).open(partfile)?;
+    if let Some(hasher) = hasher {
+        io::copy(&mut file, hasher)?;
+    }
+    Ok(file)
+}
+
+/// # How an extant file may be checked for upstream changes
+#[derive(Debug, Clone, Copy, Default)]
+struct Revalidate<'a> {
+    /// Whether it changed since this time
+    since: Option<SystemTime>,
+
+    /// Whether it no longer matches this `ETag`
+    etag: Option<&'a str>,
+}
+
+impl Revalidate<'_> {
+    const fn is_none(&self) -> bool { self.since.is_none() && self.etag.is_none() }
+}
+
+/// # A successfully downloaded file
+#[derive(Debug)]
+struct Fetched {
+    bytes: u64,
+    etag:  Option<String>,
+}
+
+/// # Downloads a single URL to a file
+///
+/// Unlike sources, the file isn't checksummed, resumed, or stored, so this is only for one-off
+/// downloads like imported profiles.
+pub async fn fetch(url: &str, path: &Path) -> Result<(), DownloadError> {
+    let resp = client_for(url).get(url).send().await?.error_for_status()?;
+    let mut file = File::create(path)?;
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let data = chunk?;
+        file.write_all(&data)?;
+        if let Some(limit) = &*RATE_LIMIT {
+            limit.throttle(data.len() as u64).await;
+        }
+    }
+    file.flush()?;
     Ok(())
 }
 
+/// # Fetches the size of a remote file
+///
+/// Issues a `HEAD` request and reads `Content-Length`. Returns `None` if the server doesn't support
+/// `HEAD` or doesn't report a length.
+pub async fn content_length(url: &str) -> Option<u64> {
+    if let Some(path) = local_path(url) {
+        return fs::metadata(path).ok().map(|m| m.len())
+    }
+
+    let resp = client_for(url).head(url).send().await.ok()?.error_for_status().ok()?;
+    resp.headers().get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+/// # The reachability of a source URL
+#[derive(Debug)]
+pub enum UrlStatus {
+    /// The server responded successfully
+    Ok(StatusCode),
+
+    /// The server responded with an error status
+    Failed(StatusCode),
+
+    /// The server could not be reached at all
+    Unreachable,
+}
+
+impl UrlStatus {
+    #[inline]
+    pub const fn is_ok(&self) -> bool { matches!(self, Self::Ok(_)) }
+}
+
+impl fmt::Display for UrlStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Self::Ok(s) => write!(f, "ok ({})", s.as_u16()),
+            | Self::Failed(s) => write!(f, "{}", s.as_u16()),
+            | Self::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+/// # Checks whether a URL is reachable without downloading it
+///
+/// Issues a `HEAD` request, falling back to a ranged `GET` of a single byte for servers that don't
+/// support `HEAD`.
+pub async fn check_url(url: &str) -> UrlStatus {
+    // Local sources are reachable if they exist, reported as the equivalent HTTP status
+    if let Some(path) = local_path(url) {
+        return match path.is_file() {
+            | true => UrlStatus::Ok(StatusCode::OK),
+            | false => UrlStatus::Failed(StatusCode::NOT_FOUND),
+        }
+    }
+
+    let client = client_for(url);
+    let status = match client.head(url).send().await {
+        | Ok(resp) if resp.status().is_success() => return UrlStatus::Ok(resp.status()),
+        | Ok(resp) => Some(resp.status()),
+        | Err(e) => {
+            debug!("HEAD request to '{url}' failed: {e}");
+            None
+        },
+    };
+
+    match client.get(url).header(RANGE, "bytes=0-0").send().await {
+        | Ok(resp) if resp.status().is_success() => UrlStatus::Ok(resp.status()),
+        | Ok(resp) => UrlStatus::Failed(resp.status()),
+        | Err(e) => {
+            debug!("Ranged GET request to '{url}' failed: {e}");
+            status.map_or(UrlStatus::Unreachable, UrlStatus::Failed)
+        },
+    }
+}
+
+/// # Progress bars for a batch of downloads
+///
+/// Each active download gets its own bar, which is cleared once it's done, and there may be an
+/// aggregate bar for the whole batch. Nothing is drawn unless stderr is a terminal.
+#[derive(Clone)]
+struct Progress {
+    bars:    MultiProgress,
+    total:   Option<ProgressBar>,
+    /// How many bytes each source has added to the aggregate bar
+    counted: Arc<Mutex<BTreeMap<String, u64>>>,
+}
+
+impl Progress {
+    fn new(total: Option<ProgressBar>) -> Self {
+        let bars = MultiProgress::with_draw_target(ProgressDrawTarget::stderr());
+        let total = total.map(|pb| bars.add(pb));
+        Self {
+            bars,
+            total,
+            counted: Arc::default(),
+        }
+    }
+
+    /// # Adds a bar for a single download
+    ///
+    /// `done` is how much of it was already downloaded, as when resuming. A source's bar is started
+    /// again for every attempt, so whatever it added to the aggregate bar before is replaced by
+    /// `done` rather than counted twice.
+    #[allow(clippy::expect_used, clippy::literal_string_with_formatting_args)]
+    fn start(&self, dest: &str, len: Option<u64>, done: u64) -> ProgressBar {
+        let template = match len {
+            | Some(_) => "{msg:24!} [{bar:40}] {bytes}/{total_bytes} ({bytes_per_sec})",
+            | None => "{msg:24!} {spinner} {bytes} ({bytes_per_sec})",
+        };
+
+        let pb = ProgressBar::new(len.unwrap_or_default())
+            .with_style(
+                ProgressStyle::with_template(template)
+                    .expect("Progress template is invalid")
+                    .progress_chars("=> "),
+            )
+            .with_message(dest.to_string())
+            .with_position(done)
+            .with_finish(ProgressFinish::AndClear);
+
+        if let Some(total) = &self.total {
+            let previous = self.counted.lock().unwrap_or_else(PoisonError::into_inner).insert(dest.to_string(), done);
+            total.set_position(total.position().saturating_sub(previous.unwrap_or(0)) + done);
+        }
+
+        // Keep the aggregate bar at the bottom
+        match &self.total {
+            | Some(total) => self.bars.insert_before(total, pb),
+            | None => self.bars.add(pb),
+        }
+    }
+
+    /// # Advances a download's bar and the aggregate bar
+    fn inc(&self, dest: &str, pb: &ProgressBar, n: u64) {
+        pb.inc(n);
+        if let Some(total) = &self.total {
+            if let Some(counted) = self.counted.lock().unwrap_or_else(PoisonError::into_inner).get_mut(dest) {
+                *counted += n;
+            }
+            total.inc(n);
+        }
+    }
+
+    /// # Clears every bar
+    fn finish(&self) {
+        if let Some(total) = &self.total {
+            total.finish_and_clear();
+        }
+        self.bars.clear().ok();
+    }
+}
+
+/// # Creates an aggregate progress bar for the downloads that would be performed
+///
+/// Sizes are fetched concurrently with `HEAD` requests. Sources whose size can't be determined are
+/// still downloaded, but don't count toward the total.
+async fn total_progress(dls: &[Download], sources_dir: &Path, download_extant: bool) -> ProgressBar {
+    let pending = dls
+        .iter()
+        .filter(|dl| dl.git_ref.is_none() && (download_extant || !sources_dir.join(&dl.dest).exists()))
+        .collect::<Vec<_>>();
+
+    let sizes = join_all(pending.iter().map(|dl| content_length(&dl.url))).await;
+    let unknown = sizes.iter().filter(|s| s.is_none()).count();
+    let total = sizes.iter().flatten().sum();
+
+    info!("Downloading {} across {} sources", HumanBytes(total), pending.len());
+    if unknown > 0 {
+        warn!("Could not determine the size of {unknown} sources");
+    }
+
+    let pb = ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::stderr());
+    #[allow(clippy::expect_used, clippy::literal_string_with_formatting_args)]
+    pb.set_style(
+        ProgressStyle::with_template("{msg} [{bar:40}] {bytes}/{total_bytes} ({percent}%, {eta})")
+            .expect("Progress template is invalid")
+            .progress_chars("=> "),
+    );
+    pb.set_message(format!("{} sources", pending.len()));
+    pb
+}
+
 impl Profile {
-    pub async fn download_sources(&self, download_extant: bool) -> Result<(), DownloadError> {
+    /// # Downloads the sources for a profile
+    ///
+    /// Returns a report of what happened to each source. If `opts.report` is set, the report is also
+    /// written there as JSON.
+    pub async fn download_sources(&self, opts: &DownloadOptions) -> Result<DownloadReport, DownloadError> {
         let sources_dir = self.sources_dir();
         if !sources_dir.exists() {
             fs::create_dir_all(&sources_dir)?;
         }
 
         let failed = Arc::new(AtomicBool::new(false));
+        let cancel = CancellationToken::new();
+
+        // Cap the number of simultaneous transfers, so large profiles don't get rate limited or run
+        // out of file descriptors
+        let permits = Arc::new(Semaphore::new(jobs().max(1)));
+
+        let mut dls = self.read_dls()?;
+        opts.filter(&mut dls);
+        if opts.resume && !self.retain_previously_failed(&mut dls) {
+            return Ok(DownloadReport::default())
+        }
+        if opts.frozen {
+            self.pin_to_lock(&mut dls)?;
+        }
 
-        let dls = self.read_dls()?;
         trace!("Here's what dls looks like:\n {dls:#?}");
+        let url_map = read_source_map(&self.source_urls_file());
+        let etags = read_source_map(&self.source_etags_file());
         let mut tasks = Vec::new();
 
+        let progress = Progress::new(match opts.show_total {
+            | true => Some(total_progress(&dls, &sources_dir, opts.force).await),
+            | false => None,
+        });
+
         for dl in dls {
             let failed = Arc::clone(&failed);
+            let permits = Arc::clone(&permits);
+            let cancel = cancel.clone();
+            let fail_fast = opts.fail_fast;
+            let retries = opts.retries.unwrap_or(CONFIG.download_retries);
             let dest = sources_dir.join(&dl.dest);
+            let progress = progress.clone();
+
+            // A changed URL means the extant file is most likely stale, e.g. a version bump that kept
+            // the destination name
+            let url_changed = url_map.get(&dl.dest).is_some_and(|url| *url != dl.url);
+            if url_changed && dest.exists() {
+                info!("Source URL changed for '{}', re-downloading", dl.dest);
+            }
+            let force = opts.force || url_changed;
+            let since = opts.since;
+            let etag = etags.get(&dl.dest).filter(|_| opts.etags).cloned();
 
             let task = task::spawn(async move {
-                if let Err(e) = download_file(&dl.url, &dest, download_extant)
-                    .await
-                    .permit(|e| matches!(e, DownloadError::Extant(_)))
-                {
-                    error!("Failed to download {} to {}: {e}", dl.url, dest.display());
-                    failed.store(false, Ordering::Relaxed);
+                let start = Instant::now();
+                let revalidate = Revalidate { since, etag: etag.as_deref() };
+                let download = async {
+                    let _permit = permits.acquire().await;
+                    download_with_mirrors(&dl, &dest, retries, force, revalidate, &progress).await
+                };
+                let outcome = tokio::select! {
+                    biased;
+                    // An interrupted download keeps its part file, so the next run can resume it
+                    () = cancel.cancelled() => Outcome::Cancelled,
+                    result = download => match result {
+                        | Ok(Fetched { bytes, etag }) => Outcome::Fetched { bytes, etag },
+                        | Err(DownloadError::Extant(_)) => Outcome::Skipped,
+                        | Err(e) => {
+                            error!("Failed to download {} to {}: {e}", dl.url, dest.display());
+                            failed.store(true, Ordering::Relaxed);
+                            if fail_fast {
+                                cancel.cancel();
+                            }
+                            Outcome::Failed { error: e.to_string() }
+                        },
+                    },
+                };
+
+                SourceReport {
+                    url: dl.url,
+                    dest: dl.dest,
+                    outcome,
+                    secs: start.elapsed().as_secs_f64(),
                 }
             });
 
             tasks.push(task);
         }
 
-        join_all(tasks).await;
+        let report = DownloadReport {
+            sources: join_all(tasks).await.into_iter().filter_map(Result::ok).collect(),
+        };
+
+        progress.finish();
+
+        self.record_outcomes(&report, url_map, etags)?;
+
+        if let Some(path) = &opts.report {
+            report.write(path)?;
+            info!("Wrote download report to '{}'", path.display());
+        }
+
+        // Name the source that caused the remaining downloads to be cancelled
+        if opts.fail_fast
+            && let Some(e) = report.first_failure()
+        {
+            return Err(e);
+        }
+
         if failed.load(Ordering::Relaxed) {
-            error!("Failed to download one or more sources");
-            exit(1)
+            return Err(DownloadError::Failed(report.failed().count()));
+        }
+
+        Ok(report)
+    }
+
+    /// # Records the outcomes of a download run for the next one
+    ///
+    /// This updates the source URL map, the stored `ETag`s, and the list of sources to retry with
+    /// `--resume-download`. `ETag`s are stored even if they weren't used for revalidation, so they're
+    /// on hand the first time they are.
+    fn record_outcomes(&self, report: &DownloadReport, mut url_map: BTreeMap<String, String>, mut etags: BTreeMap<String, String>) -> io::Result<()> {
+        // Remember where each source on disk came from. Failed sources keep their old URL, so a
+        // change is still detected on the next run.
+        for source in &report.sources {
+            if matches!(source.outcome, Outcome::Fetched { .. } | Outcome::Skipped) {
+                url_map.insert(source.dest.clone(), source.url.clone());
+            }
+        }
+        write_source_map(&self.source_urls_file(), &url_map)?;
+
+        // Remember the ETags of fetched sources, forgetting those of sources fetched without one
+        for source in &report.sources {
+            match &source.outcome {
+                | Outcome::Fetched { etag: Some(etag), .. } => etags.insert(source.dest.clone(), etag.clone()),
+                | Outcome::Fetched { etag: None, .. } => etags.remove(&source.dest),
+                | _ => None,
+            };
+        }
+        write_source_map(&self.source_etags_file(), &etags)?;
+        self.record_lock(report)?;
+
+        // Record failed and cancelled sources so they can be retried with `--resume-download`
+        let previous = fs::read_to_string(self.failed_sources_file()).unwrap_or_default();
+        let failed_dests = failed_sources(&previous, report);
+        if failed_dests.is_empty() {
+            if self.failed_sources_file().exists() {
+                fs::remove_file(self.failed_sources_file())?;
+            }
+        } else {
+            fs::write(self.failed_sources_file(), failed_dests.join("\n") + "\n")?;
         }
 
         Ok(())
@@ -165,28 +1317,289 @@ impl Profile {
 
     /// # Read dl's from the sources file
     ///
-    /// Will fail if the path does not exist, could not be read, contains invalid UTF-8, among other
-    /// errors (basically anywhere `read_to_string()` would fail).
-    #[inline]
+    /// The sources file is executed and its output parsed line by line as it's produced, so the
+    /// whole list is never held in memory as text. Will fail if the sources file could not be
+    /// executed, or if any line isn't valid UTF-8 or isn't a valid download, naming every such
+    /// line's number.
+    ///
+    /// If checksums are required, see [`require_checksum`], this also fails if any source lacks one.
     pub fn read_dls(&self) -> Result<Vec<Download>, DownloadError> {
-        String::from_utf8(Command::new(self.sources_file()).env("ENVS", self.envs_dir().as_os_str()).output()?.stdout)?
-            .lines()
-            .filter(|l| !is_comment(l))
-            .map(|l| strip_comment_part(l).to_string())
-            .map(|dl| dl.parse())
-            .collect::<Result<_, _>>()
+        let mut child = Command::new(self.sources_file())
+            .env("ENVS", self.envs_dir().as_os_str())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::other("Sources list stdout unavailable"))?;
+        let dls = parse_dls(BufReader::new(stdout), require_checksum());
+
+        // Reap the child even if parsing failed
+        child.wait()?;
+        dls
     }
 
-    pub fn get_registered_sources(&self) -> Vec<String> {
+    /// # Records what each source on disk is in the lockfile
+    ///
+    /// Only fetched sources are hashed again. Skipped sources keep their entry if their size didn't
+    /// change, and are hashed otherwise. Repositories aren't locked.
+    fn record_lock(&self, report: &DownloadReport) -> io::Result<()> {
+        let mut lock: BTreeMap<String, LockedSource> = read_source_map(&self.sources_lock_file());
+        for source in &report.sources {
+            let path = self.sources_dir().join(&source.dest);
+            let Ok(meta) = fs::metadata(&path) else { continue };
+            if !meta.is_file() {
+                continue
+            }
+
+            let locked = lock.get(&source.dest).filter(|l| l.url == source.url && l.bytes == meta.len());
+            if matches!(source.outcome, Outcome::Skipped) && locked.is_some() {
+                continue
+            }
+
+            lock.insert(source.dest.clone(), LockedSource {
+                url:    source.url.clone(),
+                bytes:  meta.len(),
+                sha256: sha256_file(&path)?,
+            });
+        }
+
+        write_source_map(&self.sources_lock_file(), &lock)
+    }
+
+    /// # Pins sources to the checksums in the lockfile
+    ///
+    /// Sources without a checksum take the locked one, so downloads that don't match it are
+    /// discarded. Fails if a source isn't locked, or its checksum differs from the locked one.
+    fn pin_to_lock(&self, dls: &mut [Download]) -> Result<(), DownloadError> {
+        let lock_file = self.sources_lock_file();
+        if !lock_file.exists() {
+            return Err(DownloadError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No lockfile at '{}'", lock_file.display()),
+            )));
+        }
+
+        let lock: BTreeMap<String, LockedSource> = read_source_map(&lock_file);
+        let mut unlocked = Vec::new();
+        for dl in dls.iter_mut().filter(|dl| dl.git_ref.is_none()) {
+            let Some(locked) = lock.get(&dl.dest) else {
+                error!("Source '{}' is not in the lockfile", dl.dest);
+                unlocked.push(dl.dest.clone());
+                continue
+            };
+
+            match &dl.sha256 {
+                | Some(sha256) if *sha256 != locked.sha256 => {
+                    return Err(DownloadError::Checksum {
+                        path:     lock_file,
+                        expected: locked.sha256.clone(),
+                        actual:   sha256.clone(),
+                    })
+                },
+                | _ => dl.sha256 = Some(locked.sha256.clone()),
+            }
+        }
+
+        if !unlocked.is_empty() {
+            return Err(DownloadError::Unlocked(unlocked));
+        }
+        Ok(())
+    }
+
+    /// # Keeps only the sources that failed during the previous download
+    ///
+    /// Returns `false` if no failures were recorded.
+    fn retain_previously_failed(&self, dls: &mut Vec<Download>) -> bool {
+        let Ok(previous) = fs::read_to_string(self.failed_sources_file()) else {
+            info!("No previously failed sources recorded for '{self}'");
+            return false
+        };
+
+        let previous = previous.lines().collect::<Vec<_>>();
+        dls.retain(|dl| previous.contains(&dl.dest.as_str()));
+        info!("Retrying {} previously failed sources for '{self}'", dls.len());
+        true
+    }
+
+    /// # Lists the destinations of the registered sources
+    ///
+    /// If `only` is given, sources not in it are left out.
+    pub fn get_registered_sources(&self, only: Option<&[String]>) -> Vec<String> {
         self.read_dls()
             .unwrap_or_else(|e| {
                 error!("Failed to read dls from sources list: {e}");
                 exit(1)
             })
-            .iter()
-            .map(|dl| dl.dest.clone())
+            .into_iter()
+            .map(|dl| dl.dest)
+            .filter(|dest| only.is_none_or(|only| only.contains(dest)))
             .collect()
     }
+
+    /// # Reads a subset of the registered sources from a file
+    ///
+    /// The file lists one destination per line. Empty lines and lines starting with '#' are
+    /// ignored. Destinations the profile doesn't register are warned about and left out. Fails if
+    /// none are left, since an empty selection would otherwise mean every source.
+    pub fn select_sources(&self, list: &Path) -> io::Result<Vec<String>> {
+        let registered = self.get_registered_sources(None);
+        let mut selected = Vec::new();
+        for dest in fs::read_to_string(list)?.lines().filter(|l| !is_comment(l)).map(str::trim) {
+            if registered.iter().any(|r| r == dest) {
+                selected.push(dest.to_string());
+            } else {
+                warn!("Source '{dest}' is not registered for '{self}', ignoring it");
+            }
+        }
+
+        if selected.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No registered sources are listed in '{}'", list.display()),
+            ));
+        }
+
+        info!("Selected {} of {} sources for '{self}'", selected.len(), registered.len());
+        Ok(selected)
+    }
+}
+
+/// # Reads a map keyed by source destination, such as the URLs or `ETag`s sources were fetched with
+///
+/// A missing or invalid map is treated as empty.
+fn read_source_map<T: DeserializeOwned>(path: &Path) -> BTreeMap<String, T> {
+    let Ok(json) = fs::read_to_string(path) else { return BTreeMap::new() };
+    serde_json::from_str(&json).unwrap_or_else(|e| {
+        warn!("Ignoring invalid source map '{}': {e}", path.display());
+        BTreeMap::new()
+    })
+}
+
+/// # Writes a map keyed by source destination
+fn write_source_map<T: Serialize>(path: &Path, map: &BTreeMap<String, T>) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(map).map_err(io::Error::other)?;
+    fs::write(path, json + "\n")
+}
+
+/// # Whether every source must have a checksum
+///
+/// Checksums are required if `require_checksum` is set in the config or `--require-checksum` is
+/// passed.
+#[inline]
+pub fn require_checksum() -> bool { CONFIG.require_checksum || REQUIRE_CHECKSUM.load(Ordering::Relaxed) }
+
+/// # Parses downloads from a reader, one per line
+///
+/// Comments and empty lines are skipped, as are exact duplicates of a source, which are warned
+/// about. Every invalid line is logged, and the error names all of them. A source sharing its
+/// destination with another but not its URL is invalid. If `require_checksum` is set, every source
+/// without a checksum is reported the same way. Invalid lines are reported before missing
+/// checksums.
+fn parse_dls<R: BufRead>(reader: R, require_checksum: bool) -> Result<Vec<Download>, DownloadError> {
+    let mut dls = Vec::new();
+    let mut unpinned = Vec::new();
+    let mut invalid = Vec::new();
+    let mut seen = BTreeMap::new();
+    for (i, line) in reader.split(b'\n').enumerate() {
+        let parsed = String::from_utf8(line?).map_err(|e| e.to_string()).and_then(|line| match is_comment(&line) {
+            | true => Ok(None),
+            | false => strip_comment_part(&line).parse::<Download>().map(Some).map_err(|e| e.to_string()),
+        });
+
+        // Keep going after an invalid line, so every one of them is reported at once
+        let dl = match parsed {
+            | Ok(Some(dl)) => dl,
+            | Ok(None) => continue,
+            | Err(reason) => {
+                error!("Invalid source at line {}: {reason}", i + 1);
+                invalid.push((i + 1, reason));
+                continue
+            },
+        };
+
+        // Two sources with the same destination would race to write the same file
+        if let Some((line, url)) = seen.get(&dl.dest) {
+            if *url == dl.url {
+                warn!("Ignoring source at line {}, a duplicate of line {line}: {dl}", i + 1);
+            } else {
+                let reason = format!("'{}' is also the destination of line {line}", dl.dest);
+                error!("Invalid source at line {}: {reason}", i + 1);
+                invalid.push((i + 1, reason));
+            }
+            continue
+        }
+        seen.insert(dl.dest.clone(), (i + 1, dl.url.clone()));
+
+        if require_checksum && dl.sha256.is_none() {
+            error!("Source at line {} has no checksum: {}", i + 1, dl.url);
+            unpinned.push(i + 1);
+        }
+        dls.push(dl);
+    }
+
+    match invalid.as_slice() {
+        | [] => {},
+        | [(line, reason)] => {
+            return Err(DownloadError::SourcesLine {
+                line:   *line,
+                reason: reason.clone(),
+            })
+        },
+        | _ => return Err(DownloadError::SourcesLines(invalid.into_iter().map(|(line, _)| line).collect())),
+    }
+
+    if !unpinned.is_empty() {
+        return Err(DownloadError::MissingChecksums(unpinned));
+    }
+    Ok(dls)
+}
+
+/// # Appends a source to the contents of a sources list
+///
+/// The line is validated before it's added, and the whole list is validated after, so checksums are
+/// enforced if required. Sources are unique by both destination and URL, so a line sharing either
+/// with a listed source is rejected.
+pub fn add_source(contents: &str, line: &str) -> Result<String, DownloadError> {
+    let dl = line.parse::<Download>()?;
+    let listed = parse_dls(contents.as_bytes(), false)?;
+    if let Some(dupe) = listed.iter().find(|l| l.dest == dl.dest || l.urls().any(|u| dl.urls().any(|v| u == v))) {
+        return Err(DownloadError::DuplicateSource(dupe.to_string()));
+    }
+
+    let mut contents = contents.to_string();
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(line);
+    contents.push('\n');
+
+    parse_dls(contents.as_bytes(), require_checksum())?;
+    Ok(contents)
+}
+
+/// # Removes the source with a destination from the contents of a sources list
+///
+/// Comments and every other line are kept as they are.
+pub fn remove_source(contents: &str, dest: &str) -> Result<String, DownloadError> {
+    // Validate the list first, so a line that merely fails to parse isn't mistaken for another
+    parse_dls(contents.as_bytes(), false)?;
+
+    let mut removed = false;
+    let kept = contents
+        .split_inclusive('\n')
+        .filter(|line| {
+            let matches = !is_comment(line)
+                && strip_comment_part(line.trim_end_matches('\n'))
+                    .parse::<Download>()
+                    .is_ok_and(|dl| dl.dest == dest);
+            removed |= matches;
+            !matches
+        })
+        .collect::<String>();
+
+    match removed {
+        | true => Ok(kept),
+        | false => Err(DownloadError::UnlistedSource(dest.to_string())),
+    }
 }
 
 /// # Check if a line is a comment or empty
@@ -208,3 +1621,288 @@ fn is_comment(line: &str) -> bool {
 fn strip_comment_part(line: &str) -> &str {
     line.rsplit_once("  #").map_or(line, |(l, _)| l)
 }
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod test {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    use super::{
+        Download, DownloadError, DownloadOptions, DownloadReport, Outcome, Progress, SourceReport, add_source, failed_sources, local_path, modified_since,
+        parse_dls, part_url_file, remove_source, resumable_part, sha256_file,
+    };
+    use crate::profile::ProfileBuf;
+
+    #[test]
+    fn parse_dls_skips_comments() {
+        let sources = b"# sources\n\nhttps://example.com/a.tar.xz\nhttps://example.com/b.tar.xz -> c.txz  # renamed\n";
+        let dls = parse_dls(&sources[..], false).unwrap_or_default();
+        let dests = dls.iter().map(|dl| dl.dest.as_str()).collect::<Vec<_>>();
+        assert_eq!(dests, ["a.tar.xz", "c.txz"]);
+    }
+
+    #[test]
+    fn sha256_file_hashes_contents() {
+        let file = tempfile::NamedTempFile::new().expect("Failed to create tempfile");
+        fs::write(file.path(), "abc").expect("Failed to write tempfile");
+        assert_eq!(
+            sha256_file(file.path()).expect("Failed to hash tempfile"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn parse_checksums() {
+        let sha256 = "9BBA0214CCF7F1079C5D59210045227BCF619519840EBFA80CD3849CFF5A5BF2";
+        let pinned = format!("https://example.com/a.tar.xz#sha256={sha256} -> b.txz")
+            .parse::<Download>()
+            .expect("Checksum should parse");
+        assert_eq!(pinned.url, "https://example.com/a.tar.xz");
+        assert_eq!(pinned.dest, "b.txz");
+        assert_eq!(pinned.sha256, Some(sha256.to_ascii_lowercase()));
+
+        let unpinned = "https://example.com/a.tar.xz".parse::<Download>().expect("Source should parse");
+        assert!(unpinned.sha256.is_none());
+
+        assert!(matches!(
+            "https://example.com/a.tar.xz#sha256=abc123".parse::<Download>(),
+            Err(DownloadError::InvalidChecksum(_))
+        ));
+    }
+
+    #[test]
+    fn parse_git_sources() {
+        let dl = "git+https://example.com/repo.git#tag=v1.2.3"
+            .parse::<Download>()
+            .expect("Repository should parse");
+        assert_eq!(dl.url, "https://example.com/repo.git");
+        assert_eq!(dl.dest, "repo");
+        assert_eq!(dl.git_ref.as_deref(), Some("v1.2.3"));
+
+        let dl = "git+https://example.com/repo#commit=abc123 -> name"
+            .parse::<Download>()
+            .expect("Repository should parse");
+        assert_eq!(dl.dest, "name");
+        assert_eq!(dl.git_ref.as_deref(), Some("abc123"));
+
+        let file = "https://example.com/a.tar.xz".parse::<Download>().expect("Source should parse");
+        assert!(file.git_ref.is_none());
+
+        assert!("git+https://example.com/repo.git".parse::<Download>().is_err());
+        assert!("git+https://example.com/repo.git#tag=".parse::<Download>().is_err());
+        assert!("git+https://example.com/repo.git#branch=--upload-pack=evil".parse::<Download>().is_err());
+        assert!(
+            "git+https://example.com/repo.git#tag=v1 | https://example.com/repo.tar.xz"
+                .parse::<Download>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn parse_signatures() {
+        let line = "https://example.com/a.tar.gz ^sig=https://example.com/a.tar.gz.sig -> b.tgz";
+        let dl = line.parse::<Download>().expect("Signature should parse");
+        assert_eq!(dl.url, "https://example.com/a.tar.gz");
+        assert_eq!(dl.sig.as_deref(), Some("https://example.com/a.tar.gz.sig"));
+        assert_eq!(dl.dest, "b.tgz");
+
+        let unsigned = "https://example.com/a.tar.gz".parse::<Download>().expect("Source should parse");
+        assert!(unsigned.sig.is_none());
+    }
+
+    #[test]
+    fn parse_local_sources() {
+        let dl = "file:///mnt/mirror/a.tar.xz -> b.txz".parse::<Download>().expect("Local source should parse");
+        assert_eq!(local_path(&dl.url), Some(Path::new("/mnt/mirror/a.tar.xz")));
+        assert_eq!(dl.dest, "b.txz");
+
+        let dl = "/mnt/mirror/a.tar.xz".parse::<Download>().expect("Local source should parse");
+        assert_eq!(local_path(&dl.url), Some(Path::new("/mnt/mirror/a.tar.xz")));
+        assert_eq!(dl.dest, "a.tar.xz");
+
+        assert!(local_path("https://example.com/a.tar.xz").is_none());
+    }
+
+    #[test]
+    fn parse_nested_dests() {
+        let dl = "https://example.com/foo.patch -> patches/foo.patch"
+            .parse::<Download>()
+            .expect("Nested destination should parse");
+        assert_eq!(dl.dest, "patches/foo.patch");
+
+        for dest in ["/etc/passwd", "../foo.patch", "patches/../../foo.patch", "./", "."] {
+            let line = format!("https://example.com/foo.patch -> {dest}");
+            assert!(
+                matches!(line.parse::<Download>(), Err(DownloadError::InvalidDest(_))),
+                "{dest} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_mirrors() {
+        let sha256 = "9bba0214ccf7f1079c5d59210045227bcf619519840ebfa80cd3849cff5a5bf2";
+        let line = format!("https://a.example/x.tar.xz | https://b.example/y.tar.xz#sha256={sha256}");
+        let dl = line.parse::<Download>().expect("Mirrors should parse");
+        assert_eq!(dl.urls().collect::<Vec<_>>(), ["https://a.example/x.tar.xz", "https://b.example/y.tar.xz"]);
+        assert_eq!(dl.dest, "x.tar.xz");
+        assert_eq!(dl.sha256.as_deref(), Some(sha256));
+
+        let conflicting = format!(
+            "https://a.example/x.tar.xz#sha256={sha256} | https://b.example/x.tar.xz#sha256={}",
+            "0".repeat(64)
+        );
+        assert!(matches!(conflicting.parse::<Download>(), Err(DownloadError::InvalidChecksum(_))));
+    }
+
+    #[test]
+    fn parse_dls_reports_line_numbers() {
+        let invalid_utf8 = b"https://example.com/a.tar.xz\n# comment\nhttps://example.com/\xff.tar.xz\n";
+        assert!(matches!(parse_dls(&invalid_utf8[..], false), Err(DownloadError::SourcesLine { line: 3, .. })));
+
+        let invalid_url = b"https://example.com/a.tar.xz\nnot-a-url\n";
+        assert!(matches!(parse_dls(&invalid_url[..], false), Err(DownloadError::SourcesLine { line: 2, .. })));
+
+        let several = b"not-a-url\nhttps://example.com/a.tar.xz\n# comment\nalso-not-a-url\nhttps://example.com/\xff.tar.xz\n";
+        assert!(matches!(parse_dls(&several[..], false), Err(DownloadError::SourcesLines(lines)) if lines == [1, 4, 5]));
+    }
+
+    #[test]
+    fn parse_dls_dedupes() {
+        let duplicate = b"https://example.com/a.tar.xz\nhttps://example.com/b.tar.xz\nhttps://example.com/a.tar.xz\n";
+        let dls = parse_dls(&duplicate[..], false).expect("Duplicates should be dropped");
+        assert_eq!(dls.iter().map(|dl| dl.dest.as_str()).collect::<Vec<_>>(), ["a.tar.xz", "b.tar.xz"]);
+
+        let conflicting = b"https://example.com/a.tar.xz\nhttps://mirror.example/x.tar.xz -> a.tar.xz\n";
+        assert!(matches!(parse_dls(&conflicting[..], false), Err(DownloadError::SourcesLine { line: 2, .. })));
+    }
+
+    #[test]
+    fn parse_dls_requires_checksums() {
+        let sha256 = "9bba0214ccf7f1079c5d59210045227bcf619519840ebfa80cd3849cff5a5bf2";
+        let sources = format!("https://example.com/a.tar.xz\nhttps://example.com/b.tar.xz#sha256={sha256}\n# comment\nhttps://example.com/c.tar.xz\n");
+        assert!(parse_dls(sources.as_bytes(), false).is_ok());
+        assert!(matches!(
+            parse_dls(sources.as_bytes(), true),
+            Err(DownloadError::MissingChecksums(lines)) if lines == [1, 4]
+        ));
+    }
+
+    #[test]
+    fn add_source_rejects_duplicates() {
+        let sources = "# sources\nhttps://example.com/a.tar.xz\nhttps://example.com/b.tar.xz -> c.txz";
+        let added = add_source(sources, "https://example.com/d.tar.xz").expect("New source should be added");
+        assert_eq!(added, format!("{sources}\nhttps://example.com/d.tar.xz\n"));
+
+        assert!(matches!(
+            add_source(sources, "https://mirror.example/a.tar.xz"),
+            Err(DownloadError::DuplicateSource(_))
+        ));
+        assert!(matches!(
+            add_source(sources, "https://example.com/b.tar.xz"),
+            Err(DownloadError::DuplicateSource(_))
+        ));
+        assert!(matches!(
+            add_source(sources, "https://example.com/x.tar.xz -> c.txz"),
+            Err(DownloadError::DuplicateSource(_))
+        ));
+        assert!(add_source(sources, "not-a-url").is_err());
+    }
+
+    #[test]
+    fn remove_source_keeps_comments() {
+        let sources = "# sources\nhttps://example.com/a.tar.xz\n\nhttps://example.com/b.tar.xz -> c.txz  # renamed\n# trailing\n";
+        let removed = remove_source(sources, "c.txz").expect("Listed source should be removed");
+        assert_eq!(removed, "# sources\nhttps://example.com/a.tar.xz\n\n# trailing\n");
+        assert!(matches!(remove_source(sources, "b.tar.xz"), Err(DownloadError::UnlistedSource(_))));
+    }
+
+    #[test]
+    fn modified_since_orderings() {
+        let since = SystemTime::UNIX_EPOCH + Duration::from_secs(1_750_000_000);
+        let hour = Duration::from_hours(1);
+        assert!(modified_since(Some(since + hour), since));
+        assert!(!modified_since(Some(since - hour), since));
+        assert!(!modified_since(Some(since), since));
+        assert!(modified_since(None, since));
+    }
+
+    #[tokio::test]
+    async fn download_sources_fails_on_bogus_url() {
+        let dir = tempfile::Builder::new().prefix("lfstage-test-").tempdir().expect("Failed to create profile");
+        let sources = dir.path().join("sources");
+        fs::write(&sources, "#!/bin/sh\necho http://127.0.0.1:1/bogus.tar.xz\n").expect("Failed to write sources");
+        fs::set_permissions(&sources, fs::Permissions::from_mode(0o755)).expect("Failed to make sources executable");
+
+        // The profile is cached within the tempdir, and a single attempt keeps the test fast
+        let profile = ProfileBuf::from_dir(dir.path()).expect("Failed to root profile");
+        let opts = DownloadOptions {
+            retries: Some(0),
+            ..Default::default()
+        };
+        let result = profile.download_sources(&opts).await;
+        assert!(matches!(result, Err(DownloadError::Failed(1))));
+
+        let failed = fs::read_to_string(dir.path().join(".cache/failed-sources")).expect("Failed to read failed sources");
+        assert_eq!(failed, "bogus.tar.xz\n");
+    }
+
+    #[test]
+    fn part_files_resume_from_the_same_url() {
+        let dir = tempfile::tempdir().expect("Failed to create tempdir");
+        let part = dir.path().join("a.tar.xz.part").to_string_lossy().to_string();
+        let url = "https://example.org/a.tar.xz";
+
+        // Nothing to resume, or nothing recorded about where it came from
+        assert_eq!(resumable_part(&part, url, false), (0, None));
+        fs::write(&part, "12345").expect("Failed to write part file");
+        assert_eq!(resumable_part(&part, url, false), (0, None));
+
+        fs::write(part_url_file(&part), format!("{url}\n\"abc\"\n")).expect("Failed to record URL");
+        assert_eq!(resumable_part(&part, url, false), (5, Some("\"abc\"".to_string())));
+        assert_eq!(resumable_part(&part, "https://mirror.example.org/a.tar.xz", false), (0, None));
+        assert_eq!(resumable_part(&part, url, true), (0, None));
+
+        fs::write(part_url_file(&part), format!("{url}\n\n")).expect("Failed to record URL");
+        assert_eq!(resumable_part(&part, url, false), (5, None));
+    }
+
+    #[test]
+    fn failed_sources_keeps_untried_failures() {
+        let source = |dest: &str, outcome| SourceReport {
+            url: format!("https://example.org/{dest}"),
+            dest: dest.to_string(),
+            outcome,
+            secs: 0.0,
+        };
+        let report = DownloadReport {
+            sources: vec![
+                source("a.tar.xz", Outcome::Failed { error: "404".to_string() }),
+                source("b.tar.xz", Outcome::Skipped),
+                source("c.tar.xz", Outcome::Cancelled),
+            ],
+        };
+
+        // b was fixed by this run, d was filtered out of it
+        let previous = "b.tar.xz\nd.tar.xz\n";
+        assert_eq!(failed_sources(previous, &report), ["a.tar.xz", "c.tar.xz", "d.tar.xz"]);
+    }
+
+    #[test]
+    fn progress_counts_restarts_once() {
+        let progress = Progress::new(Some(indicatif::ProgressBar::hidden()));
+        let total = progress.total.clone().expect("Total bar is missing");
+
+        let pb = progress.start("a.tar.xz", Some(100), 10);
+        progress.inc("a.tar.xz", &pb, 20);
+        assert_eq!(total.position(), 30);
+
+        // A retry resuming from 25 bytes replaces the 30 counted by the first attempt
+        progress.start("a.tar.xz", Some(100), 25);
+        progress.start("b.tar.xz", Some(100), 5);
+        assert_eq!(total.position(), 30);
+    }
+}
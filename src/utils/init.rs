@@ -1,29 +1,80 @@
 // utils/init.rs
 //! Initialization utilities
 
-use std::path::Path;
-use std::process::exit;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::OnceLock;
+use std::sync::Mutex;
 use std::time::Instant;
-use std::{fs, io};
+use std::{env, process};
 
+use fshelpers::mkdir_p;
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::Subscriber;
 use tracing::metadata::LevelFilter;
 use tracing_appender::non_blocking::WorkerGuard;
-use tracing_appender::rolling;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::fmt::time::FormatTime;
-use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
-use crate::config::CONFIG;
+use crate::config::{CONFIG, LogColor, LogFormat};
+use crate::utils::cmd::stop_scripts;
+use crate::utils::size::parse_size;
 
-static LOG_GUARD: OnceLock<WorkerGuard> = OnceLock::new();
-const LOG_FILE: &str = "/var/log/lfstage/lfstage.log";
+static LOG_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
 
-pub fn init() {
-    check_perms();
+/// The maximum size of the log file if `log_max_size` is invalid
+const DEFAULT_LOG_MAX_SIZE: u64 = 64 << 20;
 
-    log();
+/// # Initializes lfstage
+///
+/// `no_color` forces colorless console logs, overriding `log_color`. `needs_root` exits unless
+/// lfstage is running as root.
+pub fn init(no_color: bool, needs_root: bool) {
+    if needs_root {
+        check_perms();
+    }
+
+    log(no_color);
+}
+
+/// # Stops running scripts and exits when interrupted or terminated
+///
+/// Scripts run in their own process groups, so a Ctrl-C at the terminal only reaches lfstage, which
+/// forwards it. Without this, make and gcc would keep running after lfstage exits.
+pub fn handle_signals() {
+    tokio::spawn(async {
+        let (Ok(mut interrupt), Ok(mut terminate)) = (signal(SignalKind::interrupt()), signal(SignalKind::terminate())) else {
+            warn!("Failed to install signal handlers, scripts won't be stopped if lfstage is interrupted");
+            return
+        };
+
+        let signal = tokio::select! {
+            _ = interrupt.recv() => libc::SIGINT,
+            _ = terminate.recv() => libc::SIGTERM,
+        };
+
+        warn!("Interrupted, stopping running scripts");
+        let _ = tokio::task::spawn_blocking(move || stop_scripts(signal)).await;
+        exit(128 + signal)
+    });
+}
+
+/// # Flushes the log file and exits with a code
+///
+/// `process::exit` doesn't run destructors, so exiting directly would skip flushing the log
+/// file's background writer and lose the last lines, which are usually the error that caused the
+/// exit. Every exit should go through this instead.
+pub fn exit(code: i32) -> ! {
+    if let Ok(mut guard) = LOG_GUARD.lock() {
+        drop(guard.take());
+    }
+    process::exit(code)
 }
 
 #[inline]
@@ -53,14 +104,126 @@ impl FormatTime for Uptime {
     }
 }
 
-#[allow(clippy::expect_used, clippy::unwrap_used)]
-fn log() {
-    if let Some(parent) = Path::new(LOG_FILE).parent()
-        && !parent.exists()
-    {
-        fs::create_dir_all(parent).expect("Failed to create directory");
+/// # A log file that's rotated once it grows past a maximum size
+///
+/// Like logrotate, the full log is moved to `<path>.1`, older archives are shifted up to
+/// `<path>.<keep>`, and the oldest is dropped. The log is appended to across runs, so earlier
+/// builds stay around until they're rotated out.
+struct RotatingLog {
+    path:    PathBuf,
+    file:    File,
+    written: u64,
+    max:     u64,
+    keep:    usize,
+}
+
+impl RotatingLog {
+    /// # Opens the log file for appending, creating it and its parent directory if needed
+    fn open(path: PathBuf, max: u64, keep: usize) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            mkdir_p(parent)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            written: file.metadata()?.len(),
+            file,
+            path,
+            max,
+            keep,
+        })
     }
-    fs::write(LOG_FILE, "").expect("Failed to truncate log file");
+
+    /// # The path of the nth archive
+    fn archive(&self, n: usize) -> PathBuf {
+        let mut archive = OsString::from(&self.path);
+        archive.push(format!(".{n}"));
+        archive.into()
+    }
+
+    /// # Moves the log to the first archive, shifting the others up, and starts a new one
+    fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..self.keep).rev() {
+            match fs::rename(self.archive(n), self.archive(n + 1)) {
+                | Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                | _ => {},
+            }
+        }
+
+        match self.keep {
+            | 0 => fs::remove_file(&self.path)?,
+            | _ => fs::rename(&self.path, self.archive(1))?,
+        }
+
+        *self = Self::open(self.path.clone(), self.max, self.keep)?;
+        Ok(())
+    }
+}
+
+impl Write for RotatingLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max {
+            self.rotate()?;
+        }
+
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.file.flush() }
+}
+
+/// # Creates a formatting layer writing to a writer
+///
+/// Each writer gets its own layer so only the console is colored.
+fn fmt_layer<S, W>(writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let debug = cfg!(debug_assertions);
+    let layer = tracing_subscriber::fmt::layer()
+        .with_level(true)
+        .with_target(debug)
+        .with_line_number(debug)
+        .with_timer(Uptime::new())
+        .with_ansi(ansi)
+        .with_writer(writer);
+
+    match CONFIG.log_format {
+        | LogFormat::Compact => layer.compact().boxed(),
+        | LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+fn log(no_color: bool) {
+    let max_size = parse_size(&CONFIG.log_max_size);
+    let max = max_size.clone().unwrap_or(DEFAULT_LOG_MAX_SIZE);
+
+    // Logging isn't set up yet, so a log that can't be opened is reported on stderr, then again in
+    // the log itself
+    let log_path = Path::new(&CONFIG.log_path);
+    let (log_file, fallback): (Box<dyn Write + Send>, _) = match RotatingLog::open(log_path.to_path_buf(), max, CONFIG.log_keep) {
+        | Ok(log) => (Box::new(log), None),
+        | Err(e) => {
+            let fallback = env::temp_dir().join("lfstage.log");
+            eprintln!("WARNING: Failed to open the log at '{}': {e}", log_path.display());
+            match RotatingLog::open(fallback.clone(), max, CONFIG.log_keep) {
+                | Ok(log) => {
+                    eprintln!("WARNING: Logging to '{}' instead", fallback.display());
+                    (Box::new(log), Some((fallback, e)))
+                },
+                // This happens when running without root after root created the fallback
+                | Err(fallback_err) => {
+                    eprintln!("WARNING: Failed to open '{}' too: {fallback_err}", fallback.display());
+                    eprintln!("WARNING: Only logging to the console");
+                    (Box::new(io::sink()), None)
+                },
+            }
+        },
+    };
 
     let debug = cfg!(debug_assertions);
     let level = LevelFilter::from_str(&CONFIG.log_level).unwrap_or(match debug {
@@ -70,19 +233,71 @@ fn log() {
 
     let filter = EnvFilter::new(format!("{level},rustls=warn,hyper_util=warn,reqwest=warn"));
 
-    let (dir, file) = LOG_FILE.rsplit_once('/').unwrap_or((".", LOG_FILE));
-    let file_appender = rolling::never(dir, file);
-    let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
+    let (file_writer, guard) = tracing_appender::non_blocking(log_file);
 
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_level(true)
-        .with_target(debug)
-        .with_line_number(debug)
-        .with_timer(Uptime::new())
-        .with_writer(file_writer.and(io::stdout))
-        .compact()
+    let color = match CONFIG.log_color {
+        | _ if no_color => false,
+        | LogColor::Auto => io::stdout().is_terminal(),
+        | LogColor::Always => true,
+        | LogColor::Never => false,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer(file_writer, false))
+        .with(fmt_layer(io::stdout, color))
         .init();
 
-    LOG_GUARD.set(guard).expect("logs were inited more than once");
+    let previous = LOG_GUARD.lock().expect("log guard was poisoned").replace(guard);
+    assert!(previous.is_none(), "logs were inited more than once");
+
+    if let Some((fallback, e)) = fallback {
+        warn!(
+            "Failed to open the log at '{}': {e}. Logging to '{}' instead",
+            log_path.display(),
+            fallback.display()
+        );
+    }
+
+    match max_size {
+        | Ok(bytes) => debug!("Maximum log size is {bytes} bytes"),
+        | Err(e) => warn!("Invalid log_max_size: {e}. Using the default of {DEFAULT_LOG_MAX_SIZE} bytes"),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rotating_log_keeps_archives() {
+        let dir = tempfile::tempdir().expect("Failed to create tempdir");
+        let path = dir.path().join("lfstage.log");
+        let mut log = RotatingLog::open(path.clone(), 4, 2).expect("Failed to open log");
+
+        for line in ["aaa\n", "bbb\n", "ccc\n", "ddd\n"] {
+            log.write_all(line.as_bytes()).expect("Failed to write log");
+        }
+
+        let read = |p: PathBuf| fs::read_to_string(p).expect("Failed to read log");
+        assert_eq!(read(path), "ddd\n");
+        assert_eq!(read(log.archive(1)), "ccc\n");
+        assert_eq!(read(log.archive(2)), "bbb\n");
+        assert!(!log.archive(3).exists());
+    }
+
+    #[test]
+    fn rotating_log_counts_existing_size() {
+        let dir = tempfile::tempdir().expect("Failed to create tempdir");
+        let path = dir.path().join("lfstage.log");
+        fs::write(&path, "aaa\n").expect("Failed to write log");
+
+        let mut log = RotatingLog::open(path.clone(), 6, 1).expect("Failed to open log");
+        assert_eq!(log.written, 4);
+
+        log.write_all(b"bbb\n").expect("Failed to write log");
+        assert_eq!(fs::read_to_string(&path).expect("Failed to read log"), "bbb\n");
+        assert_eq!(fs::read_to_string(log.archive(1)).expect("Failed to read log"), "aaa\n");
+    }
 }
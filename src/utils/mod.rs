@@ -1,4 +1,7 @@
+pub mod cas;
 pub mod cmd;
 pub mod dl;
 pub mod init;
+pub mod sign;
+pub mod size;
 pub mod time;
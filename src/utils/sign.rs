@@ -0,0 +1,52 @@
+// utils/sign.rs
+//! Detached signatures for stage files and exports
+//!
+//! Signing is opt-in with `--sign`, and skipped with a warning if no `signing_key` is configured.
+//! The signature is written next to the signed file.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config::{CONFIG, SigningTool};
+
+/// # Signs a file with the configured key, if any
+///
+/// With gpg, `signing_key` is a key ID or fingerprint and the signature is written to `<file>.sig`.
+/// With minisign, it's the path to a secret key and the signature is written to `<file>.minisig`.
+/// Returns the path of the signature, or `None` if no key is configured.
+pub fn sign(file: &Path) -> io::Result<Option<PathBuf>> {
+    let Some(key) = &CONFIG.signing_key else {
+        warn!("Not signing '{}' since no signing_key is configured", file.display());
+        return Ok(None)
+    };
+
+    let (ext, mut cmd) = match CONFIG.signing_tool {
+        | SigningTool::Gpg => (".sig", Command::new("gpg")),
+        | SigningTool::Minisign => (".minisig", Command::new("minisign")),
+    };
+
+    let mut sig = OsString::from(file);
+    sig.push(ext);
+    let sig = PathBuf::from(sig);
+
+    match CONFIG.signing_tool {
+        | SigningTool::Gpg => cmd
+            .args(["--batch", "--yes", "--local-user", key, "--output"])
+            .arg(&sig)
+            .arg("--detach-sign")
+            .arg(file),
+        | SigningTool::Minisign => cmd.args(["-S", "-s", key, "-m"]).arg(file).arg("-x").arg(&sig),
+    };
+
+    let output = cmd.stdin(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or("signing failed");
+        return Err(io::Error::other(format!("Failed to sign '{}': {reason}", file.display())))
+    }
+
+    info!("Signed '{}' to '{}'", file.display(), sig.display());
+    Ok(Some(sig))
+}
@@ -0,0 +1,106 @@
+// utils/size.rs
+//! Utilities related to sizes
+
+/// # Parses a human-readable size in bytes
+///
+/// The size is a whole number, optionally followed by a unit, with or without a space in between,
+/// e.g. `10MB`, `10 MB`, or `10 MiB`. SI units (`kB`, `MB`, `GB`, `TB`) are powers of 1000 and IEC
+/// units (`KiB`, `MiB`, `GiB`, `TiB`) are powers of 1024. Units are case-insensitive and the
+/// trailing `B` may be omitted. A bare number is in bytes.
+///
+/// Zero is rejected, as is anything that isn't a whole, non-negative number of bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+
+    let num = num.parse::<u64>().map_err(|_| format!("Invalid size '{s}'"))?;
+    let unit = unit.trim().to_ascii_lowercase();
+    let multiplier: u64 = match unit.trim_end_matches('b') {
+        | "" => 1,
+        | "k" => 1000,
+        | "m" => 1000_u64.pow(2),
+        | "g" => 1000_u64.pow(3),
+        | "t" => 1000_u64.pow(4),
+        | "ki" => 1 << 10,
+        | "mi" => 1 << 20,
+        | "gi" => 1 << 30,
+        | "ti" => 1 << 40,
+        | _ => return Err(format!("Invalid size unit in '{s}', expected one of B, kB, MB, GB, TB, KiB, MiB, GiB, or TiB")),
+    };
+
+    // Only a single trailing B is allowed
+    if unit.ends_with("bb") {
+        return Err(format!("Invalid size unit in '{s}'"));
+    }
+
+    match num.checked_mul(multiplier) {
+        | Some(0) => Err(format!("Size '{s}' must be greater than zero")),
+        | Some(bytes) => Ok(bytes),
+        | None => Err(format!("Size '{s}' is too large")),
+    }
+}
+
+/// # Formats a size in bytes for humans
+///
+/// The size is given in the largest IEC unit it reaches, with one decimal, e.g. `1.5 GiB`. Sizes
+/// under a KiB are given in bytes.
+#[allow(clippy::cast_precision_loss)]
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1 << 10 {
+        return format!("{bytes} B")
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_size, parse_size};
+
+    #[test]
+    fn size_spellings() {
+        assert_eq!(parse_size("10MB"), Ok(10_000_000));
+        assert_eq!(parse_size("10 MB"), Ok(10_000_000));
+        assert_eq!(parse_size("10 mb"), Ok(10_000_000));
+        assert_eq!(parse_size("10M"), Ok(10_000_000));
+        assert_eq!(parse_size("10MiB"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("10 MiB"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("10Mi"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1 kB"), Ok(1000));
+        assert_eq!(parse_size("1KiB"), Ok(1024));
+        assert_eq!(parse_size("2 GiB"), Ok(2 << 30));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512 B"), Ok(512));
+    }
+
+    #[test]
+    fn size_invalid() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("0 MiB").is_err());
+        assert!(parse_size("-10MB").is_err());
+        assert!(parse_size("1.5 GiB").is_err());
+        assert!(parse_size("10 MBB").is_err());
+        assert!(parse_size("10 parsecs").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("99999999999 TiB").is_err());
+    }
+
+    #[test]
+    fn size_formatting() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(3 << 29), "1.5 GiB");
+        assert_eq!(format_size(2048 << 40), "2048.0 TiB");
+    }
+}
@@ -1,5 +1,119 @@
 // utils/time.rs
 //! Utilities related to time
 
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local, NaiveDateTime};
+
+/// The format of timestamps used in stage file names, e.g. `2025-07-04_13-37-00`
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// The length of a timestamp formatted with [`TIMESTAMP_FORMAT`]
+const TIMESTAMP_LEN: usize = "YYYY-MM-DD_HH-MM-SS".len();
+
+/// # Formats the current local time as a timestamp
+///
+/// The timestamp uses [`TIMESTAMP_FORMAT`] and can be read back with [`parse_timestamp`].
 #[inline]
-pub fn timestamp() -> String { chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string() }
+pub fn timestamp() -> String { Local::now().format(TIMESTAMP_FORMAT).to_string() }
+
+/// # Formats a Unix time as a timestamp
+///
+/// This is meant for `SOURCE_DATE_EPOCH`, so the timestamp is in UTC to come out the same on every
+/// machine. Returns `None` if the time is out of range.
+pub fn epoch_timestamp(secs: i64) -> Option<String> { DateTime::from_timestamp(secs, 0).map(|t| t.format(TIMESTAMP_FORMAT).to_string()) }
+
+/// # Parses a timestamp produced by [`timestamp`]
+///
+/// The timestamp is interpreted as local time. Returns `None` if it's malformed.
+pub fn parse_timestamp(s: &str) -> Option<SystemTime> {
+    let naive = NaiveDateTime::parse_from_str(s, TIMESTAMP_FORMAT).ok()?;
+    naive.and_local_timezone(Local).earliest().map(SystemTime::from)
+}
+
+/// # Finds the timestamp embedded in a stage file name
+///
+/// Stage files are named `lfstage-<profile>-<timestamp>.tar.xz`. Since profile names may contain
+/// anything, this scans for the last substring that parses as a timestamp.
+pub fn parse_stagefile_timestamp(filename: &str) -> Option<SystemTime> {
+    filename
+        .char_indices()
+        .rev()
+        .filter_map(|(i, _)| filename.get(i..i + TIMESTAMP_LEN))
+        .find_map(parse_timestamp)
+}
+
+/// # Parses a human-readable duration
+///
+/// The duration is a number followed by a unit, one of `s`, `m`, `h`, `d`, or `w`, e.g. `30d`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+
+    let num = num.parse::<u64>().map_err(|_| format!("Invalid duration '{s}'"))?;
+    let secs = match unit.trim() {
+        | "s" => 1,
+        | "m" => 60,
+        | "h" => 60 * 60,
+        | "d" => 60 * 60 * 24,
+        | "w" => 60 * 60 * 24 * 7,
+        | _ => return Err(format!("Invalid duration unit in '{s}', expected one of s, m, h, d, or w")),
+    };
+
+    Ok(Duration::from_secs(num.saturating_mul(secs)))
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    #[test]
+    fn timestamp_round_trip() {
+        let ts = timestamp();
+        let parsed = parse_timestamp(&ts).expect("Timestamp should parse");
+        let diff = SystemTime::now().duration_since(parsed).unwrap_or_default();
+        assert!(diff < Duration::from_secs(2));
+        assert_eq!(ts.len(), TIMESTAMP_LEN);
+    }
+
+    #[test]
+    fn timestamp_ordering() {
+        let earlier = parse_timestamp("2025-01-01_00-00-00").expect("Timestamp should parse");
+        let later = parse_timestamp("2025-01-01_00-00-01").expect("Timestamp should parse");
+        assert!(earlier < later);
+    }
+
+    #[test]
+    fn timestamp_invalid() {
+        assert!(parse_timestamp("").is_none());
+        assert!(parse_timestamp("2025-01-01").is_none());
+        assert!(parse_timestamp("2025-13-01_00-00-00").is_none());
+    }
+
+    #[test]
+    fn timestamp_from_epoch() {
+        assert_eq!(epoch_timestamp(0).as_deref(), Some("1970-01-01_00-00-00"));
+        assert_eq!(epoch_timestamp(1_751_636_220).as_deref(), Some("2025-07-04_13-37-00"));
+        assert!(epoch_timestamp(i64::MAX).is_none());
+    }
+
+    #[test]
+    fn stagefile_timestamp() {
+        let name = "lfstage-x86_64-glibc-tox-stage2-2025-07-04_13-37-00.tar.xz";
+        assert_eq!(parse_stagefile_timestamp(name), parse_timestamp("2025-07-04_13-37-00"));
+        assert!(parse_stagefile_timestamp("lfstage-testing.tar.xz").is_none());
+    }
+
+    #[test]
+    fn duration_units() {
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("30d"), Ok(Duration::from_hours(30 * 24)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::from_hours(14 * 24)));
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("d").is_err());
+    }
+}
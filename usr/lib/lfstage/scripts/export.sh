@@ -6,5 +6,18 @@ set -euo pipefail
 
 OUT="$(</tmp/lfstage/export)"
 
-# Tarball up the profile
-XZ_OPT=-9e tar cJpf "$OUT" -C "/var/lib/lfstage/profiles" "$LFSTAGE_PROFILE"
+# Copy the profile as a plain directory tree for raw exports
+if [ -f /tmp/lfstage/export-raw ]; then
+    mkdir -p "$OUT"
+    cp -a "/var/lib/lfstage/profiles/$LFSTAGE_PROFILE/." "$OUT"
+    exit 0
+fi
+
+# Tarball up the profile, compressed as LFSTAGE_EXPORT_FORMAT
+case "${LFSTAGE_EXPORT_FORMAT:-xz}" in
+    xz)   XZ_OPT=-9e tar cJpf "$OUT" -C "/var/lib/lfstage/profiles" "$LFSTAGE_PROFILE" ;;
+    zst)  tar --zstd -cpf "$OUT" -C "/var/lib/lfstage/profiles" "$LFSTAGE_PROFILE" ;;
+    gz)   tar czpf "$OUT" -C "/var/lib/lfstage/profiles" "$LFSTAGE_PROFILE" ;;
+    none) tar cpf "$OUT" -C "/var/lib/lfstage/profiles" "$LFSTAGE_PROFILE" ;;
+    *)    echo "Unknown export format '$LFSTAGE_EXPORT_FORMAT'" >&2; exit 1 ;;
+esac
@@ -7,14 +7,28 @@ set -euo pipefail
 cd "/var/lib/lfstage/profiles"
 IN="$(</tmp/lfstage/import)"
 
-if [[ "$IN" = *"://"*.t*z* ]]; then
-    curl -fL -# -C - --retry 3 -O "$IN"
-elif [[ "$IN" = *"://"* ]]; then
+# Copy a plain directory tree for raw imports
+if [ -f /tmp/lfstage/import-raw ]; then
+    DIR="${IN%/}"
+    DIR="${DIR##*/}"
+    DIR="${DIR%-lfstage}"
+    rm -rf "$DIR"
+    cp -a "$IN/." "$DIR"
+    exit 0
+fi
+
+# Clone git repositories, at a ref if one was given
+# Tarball URLs are downloaded by lfstage, so these are always repositories
+if [[ "$IN" = *"://"* ]]; then
     DIR="${IN%.git}"
     DIR="${DIR##*/}"
     DIR="${DIR%-lfstage}"
     rm -rf "$DIR"
-    git clone --depth=1 "$IN" "$DIR"
+    if [ -f /tmp/lfstage/import-ref ]; then
+        git clone --depth=1 --branch "$(</tmp/lfstage/import-ref)" "$IN" "$DIR"
+    else
+        git clone --depth=1 "$IN" "$DIR"
+    fi
     exit 0
 fi
 
@@ -14,12 +14,20 @@ cd "$LFS"
 TMPDIR="/tmp/lfstage/$LFSTAGE_PROFILE"
 
 # Mass strip
+# The marker lists paths, relative to $LFS, that shouldn't be stripped
 if [ -f "$TMPDIR/strip" ]; then
     msg "Mass stripping..."
+    mapfile -t EXCLUDE < "$TMPDIR/strip"
     find . -type f -executable -exec file {} + |
         grep 'not stripped' |
         cut -d: -f1         |
         while read -r file; do
+            for ex in "${EXCLUDE[@]}"; do
+                if [[ -n "$ex" && "$file" == "./${ex#/}"* ]]; then
+                    echo "lfstage: not stripping excluded $file"
+                    continue 2
+                fi
+            done
             echo "lfstage: stripping $file"
             strip --strip-unneeded "$file"
         done
@@ -31,9 +39,11 @@ msg "Saving stage file..."
 STAGEFILE="$(cat "/tmp/lfstage/$LFSTAGE_PROFILE/stagefilename")"
 XZ_OPT=-9e tar cJpf "$STAGEFILE" .
 
-# Add a convenience symlink
+# Add a convenience symlink for stage files saved to an installed profile's stages dir
 BASENAME="$(basename "$STAGEFILE")"
-ln -sfv "../profiles/$LFSTAGE_PROFILE/stages/$BASENAME" "/var/cache/lfstage/stages/$BASENAME"
+if [[ "$STAGEFILE" == "/var/cache/lfstage/profiles/$LFSTAGE_PROFILE/stages/$BASENAME" ]]; then
+    ln -sfv "../profiles/$LFSTAGE_PROFILE/stages/$BASENAME" "/var/cache/lfstage/stages/$BASENAME"
+fi
 
 # Finalize
 cd /
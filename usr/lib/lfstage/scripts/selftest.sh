@@ -0,0 +1,8 @@
+#!/bin/bash
+# Script run by `lfstage selftest` to check that scripts are executed correctly
+
+# Fail if the internal environment wasn't applied
+[[ $- == *e* && $- == *u* ]] && shopt -qo pipefail || exit 1
+[[ "$LFS" == "/var/lib/lfstage/mount" ]] || exit 1
+
+echo "Script execution works"